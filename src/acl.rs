@@ -0,0 +1,295 @@
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use serde::Deserialize;
+use serde::de::{Deserializer, Error as _};
+
+use crate::role::Capability;
+
+/// A path pattern of an access rule, relative to the store root. `*` matches
+/// any part of a path component, and `**` any number of whole components,
+/// including none, so `public/**` matches `public` itself too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathPattern {
+    components: Vec<String>,
+}
+impl PathPattern {
+    fn matches(&self, path: &[&str]) -> bool {
+        matches_components(&self.components, path)
+    }
+
+    /// Whether the pattern matches some path strictly below `dir`.
+    fn matches_below(&self, dir: &[&str]) -> bool {
+        for (index, pattern) in self.components.iter().enumerate() {
+            if pattern == "**" {
+                return true;
+            }
+            let Some(component) = dir.get(index) else {
+                return true;
+            };
+            if !matches_component(pattern, component) {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Whether the pattern matches every path in the subtree of `dir`, i.e. it
+    /// is a wildcard-free ancestor of `dir` followed by `**`.
+    fn matches_subtree(&self, dir: &[&str]) -> bool {
+        let Some((last, prefix)) = self.components.split_last() else {
+            return false;
+        };
+        last == "**"
+            && prefix.len() <= dir.len()
+            && prefix
+                .iter()
+                .zip(dir)
+                .all(|(pattern, component)| pattern == component)
+    }
+}
+impl FromStr for PathPattern {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let components: Vec<_> = pattern
+            .trim_matches('/')
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(str::to_string)
+            .collect();
+        for component in &components {
+            if component == "." || component == ".." {
+                return Err(format!("`{pattern}` must not contain `.` or `..`"));
+            }
+            if component.contains("**") && component != "**" {
+                return Err(format!(
+                    "`**` has to be a whole path component in `{pattern}`"
+                ));
+            }
+        }
+        Ok(Self { components })
+    }
+}
+impl<'de> Deserialize<'de> for PathPattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+fn matches_components(patterns: &[String], path: &[&str]) -> bool {
+    match patterns.split_first() {
+        None => path.is_empty(),
+        Some((pattern, rest)) if pattern == "**" => {
+            (0..=path.len()).any(|skipped| matches_components(rest, &path[skipped..]))
+        }
+        Some((pattern, rest)) => match path.split_first() {
+            Some((component, path)) => {
+                matches_component(pattern, component) && matches_components(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Match a single path component against a pattern where `*` stands for any
+/// run of characters.
+fn matches_component(pattern: &str, component: &str) -> bool {
+    let Some((first, rest)) = pattern.split_once('*') else {
+        return pattern == component;
+    };
+    let Some(mut remaining) = component.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<_> = rest.split('*').collect();
+    let last = parts.pop().unwrap_or_default();
+    for part in parts {
+        match remaining.find(part) {
+            Some(index) => remaining = &remaining[index + part.len()..],
+            None => return false,
+        }
+    }
+    remaining.len() >= last.len() && remaining.ends_with(last)
+}
+
+/// Grants the `allow`ed capabilities on the paths matching `path`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessRule {
+    path: PathPattern,
+    allow: Vec<Capability>,
+}
+
+/// The access rules of a user, on top of their role. The first rule matching a
+/// path decides what the user may do there, and paths no rule matches are off
+/// limits. A user without rules is only limited by their role.
+#[derive(Debug, Default)]
+pub struct Acl {
+    rules: Vec<AccessRule>,
+}
+impl Acl {
+    pub fn new(rules: Vec<AccessRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Whether `capability` is allowed on `path`, relative to the store root.
+    pub fn allows(&self, capability: Capability, path: &[&str]) -> bool {
+        if self.rules.is_empty() {
+            return true;
+        }
+        self.rules
+            .iter()
+            .find(|rule| rule.path.matches(path))
+            .is_some_and(|rule| rule.allow.contains(&capability))
+    }
+
+    /// Whether `capability` is allowed on `dir` and everything inside it, e.g.
+    /// to download it as an archive. Errs on the side of refusing when the
+    /// rules might treat some path inside differently.
+    pub fn allows_subtree(&self, capability: Capability, dir: &[&str]) -> bool {
+        if self.rules.is_empty() {
+            return true;
+        }
+        for rule in &self.rules {
+            if rule.path.matches_subtree(dir) {
+                return rule.allow.contains(&capability);
+            }
+            if rule.path.matches(dir) || rule.path.matches_below(dir) {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Whether `capability` might be allowed somewhere below `dir`, so a user
+    /// who can't read `dir` itself can still browse through it.
+    /// Without rules, what is allowed below `dir` is what is allowed on it.
+    pub fn allows_below(&self, capability: Capability, dir: &[&str]) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.allow.contains(&capability) && rule.path.matches_below(dir))
+    }
+}
+
+/// The components of `path`, relative to `store_path`, as matched by access
+/// rules. `None` for paths outside of the store.
+pub fn relative_components<'a>(path: &'a Path, store_path: &Path) -> Option<Vec<&'a str>> {
+    path.strip_prefix(store_path)
+        .ok()?
+        .components()
+        .map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Capability::*;
+
+    fn pattern(pattern: &str) -> PathPattern {
+        pattern.parse().unwrap()
+    }
+
+    fn path(path: &str) -> Vec<&str> {
+        path.split('/').filter(|c| !c.is_empty()).collect()
+    }
+
+    fn rule(path: &str, allow: &[Capability]) -> AccessRule {
+        AccessRule {
+            path: pattern(path),
+            allow: allow.to_vec(),
+        }
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(pattern("public/**").matches(&path("public")));
+        assert!(pattern("public/**").matches(&path("public/a/b.txt")));
+        assert!(!pattern("public/**").matches(&path("publicity")));
+        assert!(pattern("/docs/*.pdf").matches(&path("docs/a.pdf")));
+        assert!(!pattern("docs/*.pdf").matches(&path("docs/sub/a.pdf")));
+        assert!(pattern("**/*.pdf").matches(&path("docs/sub/a.pdf")));
+        assert!(pattern("a*b*c").matches(&path("abc")));
+        assert!(pattern("a*b*c").matches(&path("a-b-b-c")));
+        assert!(!pattern("a*b*c").matches(&path("a-c-b")));
+        assert!(!pattern("a*a").matches(&path("a")));
+        assert!(pattern("**").matches(&path("")));
+        assert!(pattern("").matches(&path("")));
+        assert!(!pattern("").matches(&path("a")));
+    }
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        assert!("public/../private".parse::<PathPattern>().is_err());
+        assert!("./public".parse::<PathPattern>().is_err());
+        assert!("public/a**".parse::<PathPattern>().is_err());
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let acl = Acl::new(vec![
+            rule("public/secret/**", &[]),
+            rule("public/**", &[Read]),
+            rule("incoming/x/**", &[Read, Write]),
+        ]);
+
+        assert!(acl.allows(Read, &path("public/a.txt")));
+        assert!(!acl.allows(Write, &path("public/a.txt")));
+        assert!(!acl.allows(Read, &path("public/secret/a.txt")));
+        assert!(acl.allows(Write, &path("incoming/x/a.txt")));
+        assert!(!acl.allows(Read, &path("incoming/y/a.txt")));
+        assert!(!acl.allows(Read, &path("")));
+
+        assert!(Acl::default().allows(Delete, &path("anything")));
+    }
+
+    #[test]
+    fn subtrees_are_only_allowed_when_every_path_is() {
+        let acl = Acl::new(vec![
+            rule("public/secret/**", &[]),
+            rule("public/**", &[Read]),
+            rule("incoming/**", &[Read]),
+        ]);
+
+        assert!(acl.allows_subtree(Read, &path("incoming")));
+        assert!(acl.allows_subtree(Read, &path("incoming/a")));
+        assert!(acl.allows_subtree(Read, &path("public/other")));
+        assert!(!acl.allows_subtree(Read, &path("public")));
+        assert!(!acl.allows_subtree(Read, &path("public/secret")));
+        assert!(!acl.allows_subtree(Read, &path("")));
+        assert!(!acl.allows_subtree(Write, &path("incoming")));
+    }
+
+    #[test]
+    fn allows_browsing_towards_allowed_paths() {
+        let acl = Acl::new(vec![
+            rule("shared/*/public/**", &[Read]),
+            rule("incoming/x/**", &[Write]),
+        ]);
+
+        assert!(acl.allows_below(Read, &path("")));
+        assert!(acl.allows_below(Read, &path("shared")));
+        assert!(acl.allows_below(Read, &path("shared/team")));
+        assert!(!acl.allows_below(Read, &path("private")));
+        assert!(!acl.allows_below(Read, &path("incoming")));
+        assert!(acl.allows_below(Write, &path("incoming")));
+        assert!(!Acl::default().allows_below(Read, &path("")));
+    }
+
+    #[test]
+    fn relative_components_stay_inside_the_store() {
+        let store = Path::new("/store");
+
+        assert_eq!(
+            relative_components(Path::new("/store/a/b"), store),
+            Some(vec!["a", "b"])
+        );
+        assert_eq!(relative_components(store, store), Some(vec![]));
+        assert_eq!(relative_components(Path::new("/elsewhere"), store), None);
+    }
+}
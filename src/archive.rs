@@ -0,0 +1,501 @@
+use std::io::ErrorKind as IoErrorKind;
+use std::path::{Path as StdPath, PathBuf};
+
+use anyhow::Result;
+use async_compression::tokio::write::GzipEncoder;
+use async_zip::tokio::write::ZipFileWriter;
+use async_zip::{Compression, ZipDateTimeBuilder, ZipEntryBuilder};
+use axum::body::Body;
+use serde::Deserialize;
+use time::OffsetDateTime;
+use tokio::fs;
+use tokio::io::{AsyncWrite, AsyncWriteExt, DuplexStream, duplex};
+use tokio::task::spawn_blocking;
+use tokio_tar::{Builder as TarBuilder, Header as TarHeader, HeaderMode};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use tokio_util::io::ReaderStream;
+
+use crate::safe_path::STATE_DIR_NAME;
+use crate::upload::is_upload_artifact_sync;
+
+/// Size of the in-memory pipe between the archive writer task and the
+/// response body. The writer is suspended whenever the client falls behind,
+/// so this is the most archive data we ever hold in memory.
+const PIPE_BUFFER_SIZE: usize = 256 * 1024;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    #[serde(rename = "zip")]
+    Zip,
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Zip => "application/zip",
+            Self::Tar => "application/x-tar",
+            Self::TarGz => "application/gzip",
+        }
+    }
+}
+
+/// An entry of the directory tree being archived.
+struct ArchiveEntry {
+    /// Path relative to the archived directory, always `/`-separated.
+    name: String,
+    path: PathBuf,
+    is_dir: bool,
+    modified: OffsetDateTime,
+}
+
+/// Stream the directory tree under `dir` as an archive named `root_name`.
+///
+/// The archive is produced by a background task writing into one end of an
+/// in-memory pipe while the response body reads from the other end, so
+/// neither the archive nor any file in it is ever fully buffered. Errors
+/// after the response has started can't be reported with a status code any
+/// more; the task logs them and drops the pipe, which truncates the body.
+pub fn stream(dir: PathBuf, root_name: String, format: ArchiveFormat) -> Body {
+    let (reader, writer) = duplex(PIPE_BUFFER_SIZE);
+
+    tokio::spawn(async move {
+        if let Err(err) = write_archive(writer, &dir, &root_name, format).await {
+            eprintln!("archive: failed to stream {}: {err}", dir.display());
+        }
+    });
+
+    Body::from_stream(ReaderStream::with_capacity(reader, PIPE_BUFFER_SIZE))
+}
+
+async fn write_archive(
+    writer: DuplexStream,
+    dir: &StdPath,
+    root_name: &str,
+    format: ArchiveFormat,
+) -> Result<()> {
+    let walk_root = dir.to_path_buf();
+    let entries = spawn_blocking(move || walk(&walk_root)).await?;
+
+    match format {
+        ArchiveFormat::Zip => write_zip(writer, root_name, entries).await,
+
+        ArchiveFormat::Tar => {
+            let mut writer = write_tar(writer, root_name, entries).await?;
+            writer.shutdown().await?;
+            Ok(())
+        }
+
+        ArchiveFormat::TarGz => {
+            let mut encoder = write_tar(GzipEncoder::new(writer), root_name, entries).await?;
+            encoder.shutdown().await?;
+            Ok(())
+        }
+    }
+}
+
+async fn write_tar<W: AsyncWrite + Unpin + Send + Sync + 'static>(
+    writer: W,
+    root_name: &str,
+    entries: Vec<ArchiveEntry>,
+) -> Result<W> {
+    let mut builder = TarBuilder::new(writer);
+
+    for entry in &entries {
+        let name = if entry.name.is_empty() {
+            root_name.to_string()
+        } else {
+            format!("{root_name}/{}", entry.name)
+        };
+        if entry.is_dir {
+            if let Some(metadata) = unless_vanished(fs::metadata(&entry.path).await)? {
+                let mut header = tar_header(entry, &metadata);
+                builder
+                    .append_data(&mut header, &name, tokio::io::empty())
+                    .await?;
+            }
+        } else if let Some(mut file) = unless_vanished(fs::File::open(&entry.path).await)? {
+            let mut header = tar_header(entry, &file.metadata().await?);
+            builder.append_data(&mut header, &name, &mut file).await?;
+        }
+    }
+
+    Ok(builder.into_inner().await?)
+}
+
+async fn write_zip(
+    writer: DuplexStream,
+    root_name: &str,
+    entries: Vec<ArchiveEntry>,
+) -> Result<()> {
+    let mut zip = ZipFileWriter::with_tokio(writer);
+
+    for entry in &entries {
+        let name = if entry.name.is_empty() {
+            format!("{root_name}/")
+        } else if entry.is_dir {
+            format!("{root_name}/{}/", entry.name)
+        } else {
+            format!("{root_name}/{}", entry.name)
+        };
+        let modified = entry.modified;
+        let builder = ZipEntryBuilder::new(name.into(), Compression::Stored)
+            .last_modification_date(
+                ZipDateTimeBuilder::new()
+                    .year(modified.year())
+                    .month(u8::from(modified.month()).into())
+                    .day(modified.day().into())
+                    .hour(modified.hour().into())
+                    .minute(modified.minute().into())
+                    .second(modified.second().into())
+                    .build(),
+            );
+
+        if entry.is_dir {
+            zip.write_entry_whole(builder, &[]).await?;
+        } else if let Some(file) = unless_vanished(fs::File::open(&entry.path).await)? {
+            let mut entry_writer = zip.write_entry_stream(builder).await?;
+            futures::io::copy(file.compat(), &mut entry_writer).await?;
+            entry_writer.close().await?;
+        }
+    }
+
+    zip.close().await?.into_inner().shutdown().await?;
+
+    Ok(())
+}
+
+/// The tar header of `entry`. It keeps the modification time, but leaves out
+/// the owner, which would tell anyone downloading an archive, e.g. through a
+/// share link, about the accounts of the server.
+fn tar_header(entry: &ArchiveEntry, metadata: &std::fs::Metadata) -> TarHeader {
+    let mut header = TarHeader::new_gnu();
+    header.set_metadata_in_mode(metadata, HeaderMode::Deterministic);
+    header.set_mtime(entry.modified.unix_timestamp().max(0) as u64);
+    header
+}
+
+/// `None` for an entry that vanished or became unreadable since the walk, so
+/// it is left out like during the walk.
+fn unless_vanished<T>(result: std::io::Result<T>) -> std::io::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err)
+            if matches!(
+                err.kind(),
+                IoErrorKind::NotFound | IoErrorKind::PermissionDenied
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Walk the tree under `root` depth-first, directories before their content.
+/// Symlinks are skipped so an archive can never pull in anything from outside
+/// the store, and entries that vanish or can't be read mid-walk are ignored,
+/// as are the server's state directory and the bookkeeping files of uploads.
+fn walk(root: &StdPath) -> Vec<ArchiveEntry> {
+    let mut entries = Vec::new();
+    let mut stack = vec![(root.to_path_buf(), String::new())];
+
+    while let Some((dir, name)) = stack.pop() {
+        let Ok(metadata) = std::fs::metadata(&dir) else {
+            continue;
+        };
+        entries.push(ArchiveEntry {
+            name: name.clone(),
+            path: dir.clone(),
+            is_dir: true,
+            modified: modified_of(&metadata),
+        });
+
+        let Ok(read_dir) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut children = read_dir
+            .flatten()
+            .filter_map(|child| {
+                let file_type = child.file_type().ok()?;
+                if file_type.is_symlink() {
+                    return None;
+                }
+                let child_name = child.file_name().to_str()?.to_string();
+                if child_name == STATE_DIR_NAME || is_upload_artifact_sync(&child.path()) {
+                    return None;
+                }
+                Some((child.path(), child_name, file_type.is_dir()))
+            })
+            .collect::<Vec<_>>();
+        children.sort_unstable_by(|a, b| a.1.cmp(&b.1));
+
+        let mut subdirs = Vec::new();
+        for (path, child_name, is_dir) in children {
+            let child_name = if name.is_empty() {
+                child_name
+            } else {
+                format!("{name}/{child_name}")
+            };
+
+            if is_dir {
+                subdirs.push((path, child_name));
+            } else if let Ok(metadata) = std::fs::metadata(&path) {
+                entries.push(ArchiveEntry {
+                    name: child_name,
+                    path,
+                    is_dir: false,
+                    modified: modified_of(&metadata),
+                });
+            }
+        }
+        // Reversed so the stack pops subdirectories in name order.
+        stack.extend(subdirs.into_iter().rev());
+    }
+
+    entries
+}
+
+fn modified_of(metadata: &std::fs::Metadata) -> OffsetDateTime {
+    metadata
+        .modified()
+        .map(OffsetDateTime::from)
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
+
+/// Build a `Content-Disposition` value that makes browsers save the response
+/// as `file_name`, percent-encoding it per RFC 6266 / RFC 5987 so non-ASCII
+/// names survive.
+pub fn content_disposition(file_name: &str) -> String {
+    let mut encoded = String::with_capacity(file_name.len());
+    for byte in file_name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    format!("attachment; filename*=UTF-8''{encoded}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use tokio::io::AsyncReadExt;
+
+    fn fixture() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("b").join("nested")).unwrap();
+        std::fs::create_dir(dir.path().join("empty")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"a").unwrap();
+        std::fs::write(dir.path().join("b").join("nested").join("c.txt"), b"c").unwrap();
+        dir
+    }
+
+    async fn archive_bytes(dir: &StdPath, format: ArchiveFormat) -> Vec<u8> {
+        let (mut reader, writer) = duplex(PIPE_BUFFER_SIZE);
+        let dir = dir.to_path_buf();
+        let task = tokio::spawn(async move { write_archive(writer, &dir, "root", format).await });
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await.unwrap();
+        task.await.unwrap().unwrap();
+        bytes
+    }
+
+    #[test]
+    fn walk_lists_directories_before_their_content() {
+        let dir = fixture();
+
+        let names = walk(dir.path())
+            .into_iter()
+            .map(|entry| (entry.name, entry.is_dir))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            names,
+            vec![
+                ("".to_string(), true),
+                ("a.txt".to_string(), false),
+                ("b".to_string(), true),
+                ("b/nested".to_string(), true),
+                ("b/nested/c.txt".to_string(), false),
+                ("empty".to_string(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn walk_skips_upload_artifacts() {
+        let dir = fixture();
+        std::fs::write(dir.path().join("d.bin.resumable-upload"), b"").unwrap();
+        std::fs::write(dir.path().join("d.bin.resumable-meta"), b"{}").unwrap();
+
+        assert!(
+            walk(dir.path())
+                .iter()
+                .all(|entry| !entry.name.starts_with("d.bin"))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_skips_symlinks() {
+        let dir = fixture();
+        std::os::unix::fs::symlink("/etc", dir.path().join("link")).unwrap();
+
+        assert!(walk(dir.path()).iter().all(|entry| entry.name != "link"));
+    }
+
+    #[tokio::test]
+    async fn tar_contains_every_entry() {
+        let dir = fixture();
+
+        let bytes = archive_bytes(dir.path(), ArchiveFormat::Tar).await;
+
+        let mut archive = tokio_tar::Archive::new(bytes.as_slice());
+        let mut names = Vec::new();
+        let mut entries = archive.entries().unwrap();
+        while let Some(entry) = futures::StreamExt::next(&mut entries).await {
+            names.push(entry.unwrap().path().unwrap().to_string_lossy().to_string());
+        }
+        assert_eq!(
+            names,
+            vec![
+                "root",
+                "root/a.txt",
+                "root/b",
+                "root/b/nested",
+                "root/b/nested/c.txt",
+                "root/empty",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn tar_headers_keep_mtime_but_not_the_owner() {
+        let dir = fixture();
+        let modified = std::fs::metadata(dir.path().join("a.txt"))
+            .unwrap()
+            .modified()
+            .unwrap();
+
+        let bytes = archive_bytes(dir.path(), ArchiveFormat::Tar).await;
+
+        let mut archive = tokio_tar::Archive::new(bytes.as_slice());
+        let mut entries = archive.entries().unwrap();
+        while let Some(entry) = futures::StreamExt::next(&mut entries).await {
+            let entry = entry.unwrap();
+            let header = entry.header();
+            assert_eq!(header.uid().unwrap(), 0);
+            assert_eq!(header.gid().unwrap(), 0);
+            assert!(header.username().unwrap().unwrap_or_default().is_empty());
+            assert!(header.groupname().unwrap().unwrap_or_default().is_empty());
+            if entry.path().unwrap().ends_with("a.txt") {
+                assert_eq!(
+                    header.mtime().unwrap(),
+                    modified
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs()
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn archives_skip_files_vanished_since_the_walk() {
+        let dir = fixture();
+        let entries = walk(dir.path());
+        std::fs::remove_file(dir.path().join("a.txt")).unwrap();
+
+        let bytes = write_tar(Vec::new(), "root", entries).await.unwrap();
+
+        let mut archive = tokio_tar::Archive::new(bytes.as_slice());
+        let mut names = Vec::new();
+        let mut entries = archive.entries().unwrap();
+        while let Some(entry) = futures::StreamExt::next(&mut entries).await {
+            names.push(entry.unwrap().path().unwrap().to_string_lossy().to_string());
+        }
+        assert!(names.contains(&"root/b/nested/c.txt".to_string()));
+        assert!(!names.contains(&"root/a.txt".to_string()));
+
+        let (mut reader, writer) = duplex(PIPE_BUFFER_SIZE);
+        let entries = walk(dir.path());
+        std::fs::remove_file(dir.path().join("b/nested/c.txt")).unwrap();
+        let task = tokio::spawn(async move { write_zip(writer, "root", entries).await });
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await.unwrap();
+        task.await.unwrap().unwrap();
+
+        let reader = async_zip::base::read::mem::ZipFileReader::new(bytes)
+            .await
+            .unwrap();
+        assert!(
+            reader
+                .file()
+                .entries()
+                .iter()
+                .all(|entry| entry.filename().as_str().unwrap() != "root/b/nested/c.txt")
+        );
+    }
+
+    #[tokio::test]
+    async fn tar_gz_is_gzip_compressed() {
+        let dir = fixture();
+
+        let bytes = archive_bytes(dir.path(), ArchiveFormat::TarGz).await;
+
+        assert_eq!(&bytes[..2], &[0x1f, 0x8b]);
+    }
+
+    #[tokio::test]
+    async fn zip_contains_every_entry() {
+        let dir = fixture();
+
+        let bytes = archive_bytes(dir.path(), ArchiveFormat::Zip).await;
+
+        let reader = async_zip::base::read::mem::ZipFileReader::new(bytes)
+            .await
+            .unwrap();
+        let names = reader
+            .file()
+            .entries()
+            .iter()
+            .map(|entry| entry.filename().as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "root/",
+                "root/a.txt",
+                "root/b/",
+                "root/b/nested/",
+                "root/b/nested/c.txt",
+                "root/empty/",
+            ]
+        );
+    }
+
+    #[test]
+    fn content_disposition_percent_encodes_name() {
+        assert_eq!(
+            content_disposition("my files.zip"),
+            "attachment; filename*=UTF-8''my%20files.zip"
+        );
+        assert_eq!(
+            content_disposition("文件.tar"),
+            "attachment; filename*=UTF-8''%E6%96%87%E4%BB%B6.tar"
+        );
+    }
+}
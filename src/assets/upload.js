@@ -11,41 +11,99 @@ if (!('command' in HTMLButtonElement.prototype &&
 	}
 }
 
+// Ask for confirmation before submitting destructive forms, e.g. deleting an entry.
+for (const formElement of document.querySelectorAll('form[data-confirm]')) {
+	formElement.onsubmit = function confirmFormSubmit(event) {
+		if (!confirm(formElement.dataset.confirm)) {
+			event.preventDefault()
+		}
+	}
+}
+
+const uploadDialogElement = document.getElementById('uploadDialog')
 const uploadFormElement = document.getElementById('uploadForm')
 const fileInputElement = document.getElementById('fileInput')
+const folderInputElement = document.getElementById('folderInput')
+const conflictSelectElement = document.getElementById('conflictSelect')
 const inputGroupElement = document.getElementById('inputGroup')
 const resumableUploadElement = document.getElementById('resumableUpload')
 const uploadProgressContainerElement = document.getElementById('uploadProgressContainer')
 const uploadProgressTextElement = document.getElementById('uploadProgressText')
 const uploadProgressBarElement = document.getElementById('uploadProgressBar')
 const uploadSubmitBtnElement = document.getElementById('uploadSubmitBtn')
+const uploadCancelBtnElement = document.getElementById('uploadCancelBtn')
 
-// Enable selected file preview
-fileInputElement.onchange = function handleFilePreview() {
-	showSelectedFilePreview()
+/**
+ * Aborts the upload in progress, `null` while there is none.
+ * @type {(() => void) | null}
+ */
+let cancelCurrentUpload = null
+
+// Closing the dialog, with the close button or the Escape key, cancels the upload in progress.
+uploadCancelBtnElement.onclick = confirmCancelUpload
+uploadDialogElement.querySelector('.close-button').onclick = confirmCancelUpload
+uploadDialogElement.oncancel = confirmCancelUpload
+function confirmCancelUpload(event) {
+	if (cancelCurrentUpload == null) {
+		return
+	}
+	event.preventDefault()
+	if (confirm('Cancel the upload in progress?')) {
+		cancelCurrentUpload()
+		cancelCurrentUpload = null
+	}
+}
+
+/**
+ * Get all the selected files, from both the file and the folder picker.
+ * @returns {File[]}
+ */
+function getSelectedFiles() {
+	return [...fileInputElement.files, ...folderInputElement.files]
+}
+
+/**
+ * The path of a selected file relative to the current directory.
+ * Files picked from a folder keep the folder structure.
+ * @param {File} file
+ */
+function getRelativePath(file) {
+	return file.webkitRelativePath || file.name
 }
-fileInputElement.oncancel = function handleFileCancel() {
-	showSelectedFilePreview()
+
+// Enable selected file preview
+for (const inputElement of [fileInputElement, folderInputElement]) {
+	inputElement.onchange = function handleFilePreview() {
+		showSelectedFilePreview()
+	}
+	inputElement.oncancel = function handleFileCancel() {
+		showSelectedFilePreview()
+	}
 }
 function showSelectedFilePreview() {
-	const file = fileInputElement.files[0]
+	const files = getSelectedFiles()
 	let previewContainer = document.getElementById('selectedFilePreview')
-	if (file == null) {
+	if (files.length === 0) {
 		if (previewContainer != null) {
 			inputGroupElement.removeChild(previewContainer)
 		}
 	} else {
-		const fileType = file.name.split('.').pop().toUpperCase()
+		const fileName = files.length === 1 ? getRelativePath(files[0]) : `${files.length} files`
+		const fileSize = prettyFileSize(files.reduce((total, file) => total + file.size, 0))
+		const fileType = files.length === 1 ? files[0].name.split('.').pop().toUpperCase() : files.length
 		if (previewContainer != null) {
-			previewContainer.querySelector('#selectedFileName').innerText = file.name
-			previewContainer.querySelector('#selectedFileSize').innerText = prettyFileSize(file.size)
+			previewContainer.querySelector('#selectedFileName').innerText = fileName
+			previewContainer.querySelector('#selectedFileSize').innerText = fileSize
 			previewContainer.querySelector('#selectedFileType').innerText = fileType
 		} else {
 			previewContainer = Object.assign(document.createElement('div'), {
 				className: 'selected-file',
 				id: 'selectedFilePreview',
 			})
-			previewContainer.innerHTML = `<div class="file-preview" id="selectedFileType">${fileType}</div><div class="file-info"><div class="file-name" id="selectedFileName">${file.name}</div><div class="file-size" id="selectedFileSize">${prettyFileSize(file.size)}</div></div>`
+			previewContainer.innerHTML = '<div class="file-preview" id="selectedFileType"></div><div class="file-info"><div class="file-name" id="selectedFileName"></div><div class="file-size" id="selectedFileSize"></div></div>'
+			previewContainer.querySelector('#selectedFileName').innerText = fileName
+			previewContainer.querySelector('#selectedFileSize').innerText = fileSize
+			previewContainer.querySelector('#selectedFileType').innerText = fileType
 			previewContainer.appendChild(
 				Object.assign(document.createElement('button'), {
 					type: 'button',
@@ -53,6 +111,7 @@ function showSelectedFilePreview() {
 					innerText: '×',
 					onclick: function removeSelectedFile() {
 						fileInputElement.value = ''
+						folderInputElement.value = ''
 						showSelectedFilePreview()
 					},
 				})
@@ -71,6 +130,11 @@ resumableUploadElement.checked = true
 uploadFormElement.onsubmit = function handleUploadFormSubmit(event) {
 	event.preventDefault()
 
+	if (getSelectedFiles().length === 0) {
+		alert('Please select at least one file or folder to upload.')
+		return
+	}
+
 	if (resumableUploadElement.checked) {
 		resumableUpload()
 	} else {
@@ -81,13 +145,27 @@ uploadFormElement.onsubmit = function handleUploadFormSubmit(event) {
 	uploadSubmitBtnElement.disabled = true
 	resumableUploadElement.disabled = true
 	fileInputElement.disabled = true
+	folderInputElement.disabled = true
+	conflictSelectElement.disabled = true
+	uploadCancelBtnElement.hidden = false
 }
 
 function normalUpload() {
-	const formData = new FormData(uploadFormElement)
+	const formData = new FormData()
+	formData.append('conflict', conflictSelectElement.value)
+	for (const file of getSelectedFiles()) {
+		formData.append('file', file, getRelativePath(file))
+	}
 
 	const xhr = new XMLHttpRequest()
 
+	// Files of the form that were stored before the abort are kept.
+	let cancelled = false
+	cancelCurrentUpload = function cancelNormalUpload() {
+		cancelled = true
+		xhr.abort()
+	}
+
 	const timeStart = performance.now()
 
 	let uploadProgress = 0
@@ -118,9 +196,19 @@ function normalUpload() {
 
 	xhr.onreadystatechange = function handleXhrEnd() {
 		if (xhr.readyState === XMLHttpRequest.DONE) {
-			if (xhr.status === 200) {
+			cancelCurrentUpload = null
+			if (cancelled) {
+				window.location.reload()
+			} else if (xhr.status === 200) {
 				const timeEnd = performance.now()
-				alert(`Upload complete in ${prettyTime(timeEnd - timeStart)}!`)
+				/** @type {{ files: { name: string; error?: string }[] }} */
+				const result = JSON.parse(xhr.responseText)
+				const failedFiles = result.files.filter(file => file.error != null)
+				if (failedFiles.length === 0) {
+					alert(`Upload complete in ${prettyTime(timeEnd - timeStart)}!`)
+				} else {
+					alert(`${failedFiles.length} of ${result.files.length} files failed to upload:\n\n${failedFiles.map(file => `${file.name}: ${file.error}`).join('\n')}`)
+				}
 				window.location.reload()
 			} else {
 				alert('Upload failed: ' + xhr.statusText)
@@ -129,52 +217,165 @@ function normalUpload() {
 	}
 
 	xhr.open('POST', uploadFormElement.action, true)
+	xhr.setRequestHeader('Accept', 'application/json')
 	xhr.send(formData)
 }
 
 async function resumableUpload() {
-	const uriPrefix = uploadFormElement.getAttribute('action').replace('/files/', '/upload/')
-	/** @type File */
-	const file = fileInputElement.files[0]
+	const files = getSelectedFiles()
+	const totalBytes = files.reduce((total, file) => total + file.size, 0)
+
+	const abortController = new AbortController()
+	cancelCurrentUpload = function cancelResumableUpload() {
+		abortController.abort()
+	}
 
 	const timeStart = performance.now()
 
-	let uploadProgress = 0
+	let uploadedBytes = 0
+	let currentFileProgress = 0
+	let currentFileSize = 0
 	let stopProgressBarAnimation = startProgressBarAnimation({
 		getProgress() {
-			return uploadProgress
+			if (totalBytes === 0) {
+				return 0
+			}
+			return (uploadedBytes + currentFileProgress * currentFileSize) / totalBytes
 		},
 	})
 	showUploadProgress()
 
+	/** @type string[] */
+	const failedFiles = []
+
+	// Files are uploaded one after another, each of them is already uploaded in parallel chunks.
+	for (const file of files) {
+		currentFileProgress = 0
+		currentFileSize = file.size
+		try {
+			if (file.size === 0) {
+				// An empty file has no chunks to resume, upload it with the plain form endpoint.
+				await uploadEmptyFile(file, getRelativePath(file), abortController.signal)
+			} else {
+				await resumableUploadFile(file, getRelativePath(file), abortController.signal, progress => {
+					currentFileProgress = progress
+				})
+			}
+		} catch (_) {
+			if (abortController.signal.aborted) {
+				// Files uploaded before the cancellation are kept, only the current one is discarded.
+				if (file.size !== 0) {
+					await deleteResumableUpload(getResumableUploadUri(getRelativePath(file)))
+				}
+				break
+			}
+			failedFiles.push(getRelativePath(file))
+		}
+		uploadedBytes += file.size
+		currentFileProgress = 0
+	}
+	cancelCurrentUpload = null
+
+	const timeEnd = performance.now()
+
+	if (stopProgressBarAnimation != null) {
+		stopProgressBarAnimation()
+		stopProgressBarAnimation = null
+	}
+
+	hideUploadProgress()
+
+	if (abortController.signal.aborted) {
+		window.location.reload()
+		return
+	}
+
+	if (failedFiles.length === 0) {
+		alert(`Upload complete in ${prettyTime(timeEnd - timeStart)}!`)
+	} else {
+		alert(`${failedFiles.length} of ${files.length} files failed to upload:\n\n${failedFiles.join('\n')}`)
+	}
+	window.location.reload()
+}
+
+/**
+ * Upload an empty file through the multipart form endpoint.
+ * @param {File} file - The empty file to upload.
+ * @param {string} relativePath - The path of the file relative to the current directory.
+ * @param {AbortSignal} signal - Aborts the upload.
+ * @returns {Promise<void>} - Resolves once the file is stored, rejects otherwise.
+ */
+async function uploadEmptyFile(file, relativePath, signal) {
+	const formData = new FormData()
+	formData.append('conflict', conflictSelectElement.value)
+	formData.append('file', file, relativePath)
+
+	const response = await fetch(uploadFormElement.action, {
+		method: 'POST',
+		headers: {
+			'Accept': 'application/json',
+		},
+		body: formData,
+		signal,
+	})
+	if (!response.ok) {
+		throw new Error(response.statusText)
+	}
+
+	/** @type {{ files: { name: string; error?: string }[] }} */
+	const result = await response.json()
+	if (result.files.some(file => file.error != null)) {
+		throw new Error(`Failed to upload ${relativePath}`)
+	}
+}
+
+/**
+ * Upload a single file with the resumable upload API.
+ * @param {File} file - The file to upload.
+ * @param {string} relativePath - The path of the file relative to the current directory.
+ * @param {AbortSignal} signal - Aborts the upload, the chunks uploaded so far are kept on the server.
+ * @param {(progress: number) => void} onProgress - Called with the upload progress of the file (0 - 1).
+ * @returns {Promise<void>} - Resolves once all chunks are uploaded, rejects otherwise.
+ */
+async function resumableUploadFile(file, relativePath, signal, onProgress) {
+	const fileUri = getResumableUploadUri(relativePath)
+
 	/**
-	 * @type {{ chunkSize: number; fileSize: number; chunks: Record<number, (0 | 1 | 2)> }}
+	 * @type {{ chunkSize: number; fileSize: number; totalChunks: number; missingChunks: [number, number][] }}
 	 */
 	let meta
 
-	const getMetaResponse = await fetch(`${uriPrefix}${file.name}`)
+	const getMetaResponse = await fetch(fileUri, { signal })
 	if (getMetaResponse.ok) {
 		meta = await getMetaResponse.json()
 	} else {
-		meta = await (await fetch(`${uriPrefix}${file.name}`, {
+		const createMetaResponse = await fetch(fileUri, {
 			method: 'POST',
 			headers: {
 				'Content-Type': 'application/json',
 			},
 			body: JSON.stringify({
 				size: file.size,
+				chunkSize: preferredChunkSize(),
+				conflict: conflictSelectElement.value,
 			}),
-		})).json()
+			signal,
+		})
+		if (!createMetaResponse.ok) {
+			throw new Error(createMetaResponse.statusText)
+		}
+		meta = await createMetaResponse.json()
 	}
 
-	const totalChunks = Object.keys(meta.chunks).length
-	let completedChunks = Object.values(meta.chunks).filter(status => status === 2).length
-	uploadProgress = completedChunks / totalChunks
+	const totalChunks = meta.totalChunks
+	let completedChunks = totalChunks - meta.missingChunks.reduce((count, [start, end]) => count + end - start, 0)
+	let allChunksCompleted = false
+	onProgress(completedChunks / totalChunks)
 
 	// TODO: We should ask the server to mark all ongoing chunks as not started before we start uploading,
 	// for now let's just assume all chunks that are not completed are all not started.
 
-	const unuploadedChunkIndexes = Object.entries(meta.chunks).filter(([_, status]) => status !== 2).map(([index, _]) => parseInt(index, 10)).reverse()
+	const unuploadedChunkIndexes = meta.missingChunks.flatMap(([start, end]) => Array.from({ length: end - start }, (_, i) => start + i)).reverse()
 	await makePromisePool(6, () => {
 		const chunkIndex = unuploadedChunkIndexes.pop()
 
@@ -185,19 +386,22 @@ async function resumableUpload() {
 		const chunkedFile = file.slice(chunkIndex * meta.chunkSize, (chunkIndex + 1) * meta.chunkSize)
 		return (async function uploadChunk() {
 			const data = await chunkedFile.arrayBuffer()
+			const contentDigest = await getContentDigest(data)
 
 			let retryTimes = 3
 
-			while (retryTimes > 0) {
+			while (retryTimes > 0 && !signal.aborted) {
 				/** @type { success: boolaen; allChunksCompleted: boolean } */
 				let uploadResult
 				try {
-					const response = await fetch(`${uriPrefix}${file.name}`, {
+					const response = await fetch(fileUri, {
 						method: 'PUT',
 						headers: {
 							'Resumable-Upload-Chunk-Index': chunkIndex,
+							...(contentDigest != null ? { 'Content-Digest': contentDigest } : {}),
 						},
 						body: data,
+						signal,
 					})
 
 					if (response.status === 429) {
@@ -216,20 +420,10 @@ async function resumableUpload() {
 					continue
 				} else {
 					completedChunks += 1
-					uploadProgress = completedChunks / totalChunks
+					onProgress(completedChunks / totalChunks)
 
 					if (uploadResult.allChunksCompleted) {
-						const timeEnd = performance.now()
-
-						if (stopProgressBarAnimation != null) {
-							stopProgressBarAnimation()
-							stopProgressBarAnimation = null
-						}
-
-						hideUploadProgress()
-
-						alert(`Upload complete in ${prettyTime(timeEnd - timeStart)}!`)
-						window.location.reload()
+						allChunksCompleted = true
 					}
 
 					break
@@ -237,6 +431,50 @@ async function resumableUpload() {
 			}
 		})()
 	})
+
+	if (!allChunksCompleted) {
+		throw new Error(`Failed to upload ${relativePath}`)
+	}
+}
+
+/**
+ * Compute the `Content-Digest` header of a chunk, so the server can detect
+ * corruption in transit. WebCrypto is only available in secure contexts,
+ * over plain HTTP the chunk is sent without a digest.
+ * @param {ArrayBuffer} data - The chunk data.
+ * @returns {Promise<string | null>}
+ */
+async function getContentDigest(data) {
+	if (window.crypto == null || window.crypto.subtle == null) {
+		return null
+	}
+	const digest = new Uint8Array(await window.crypto.subtle.digest('SHA-256', data))
+	return `sha-256=:${btoa(String.fromCharCode(...digest))}:`
+}
+
+/**
+ * The resumable upload API URI of a file.
+ * @param {string} relativePath - The path of the file relative to the current directory.
+ */
+function getResumableUploadUri(relativePath) {
+	const uriPrefix = uploadFormElement.getAttribute('action').replace('/files/', '/upload/')
+	return `${uriPrefix}${relativePath.split('/').map(encodeURIComponent).join('/')}`
+}
+
+/**
+ * Cancel a resumable upload, discarding the chunks uploaded so far.
+ * The server refuses while chunks of the upload are still in flight,
+ * which they are for a moment after the requests got aborted.
+ * @param {string} fileUri - The resumable upload API URI of the file.
+ */
+async function deleteResumableUpload(fileUri) {
+	for (let attempt = 0; attempt < 10; ++attempt) {
+		const response = await fetch(fileUri, { method: 'DELETE' })
+		if (response.status !== 409) {
+			return
+		}
+		await sleep(getRetryAfterMs(response))
+	}
 }
 
 function getRetryAfterMs(response) {
@@ -293,6 +531,19 @@ function startProgressBarAnimation(options) {
 	}
 }
 
+/**
+ * The chunk size to ask the server for. Small chunks lose less progress when a
+ * flaky mobile connection drops, elsewhere the server default is used.
+ * @returns {number | undefined} - The chunk size in bytes, or `undefined` for the server default.
+ */
+function preferredChunkSize() {
+	const connection = navigator.connection
+	if (connection && (connection.saveData || ['slow-2g', '2g', '3g'].includes(connection.effectiveType))) {
+		return 2 * 1024 * 1024
+	}
+	return undefined
+}
+
 /**
  * Format a file size in bytes to a human-readable string.
  * @param {number} fileSizeInBytes - The file size in bytes.
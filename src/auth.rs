@@ -1,18 +1,34 @@
-use std::sync::LazyLock;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 use axum::RequestPartsExt;
 use axum::extract::{FromRequestParts, OptionalFromRequestParts};
+use axum::http::HeaderMap;
+use axum::http::header::ACCEPT;
 use axum::http::request::Parts;
 use axum_extra::{
     TypedHeader,
-    headers::{Authorization, Cookie, authorization::Bearer},
+    headers::{
+        Authorization, Cookie,
+        authorization::{Basic, Bearer},
+    },
 };
 use form_urlencoded::byte_serialize as encode_uri;
 use jsonwebtoken::{DecodingKey, EncodingKey, Validation, decode};
+use rand::{RngExt, rng};
 use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
 
+use crate::acl::{Acl, relative_components};
 use crate::config::CONFIG;
 use crate::errors::ServerError;
+use crate::role::{Capability, Role};
+
+/// Sent with `401 Unauthorized` to clients that aren't browsers, so tools
+/// like `wget --user` know to retry with their credentials.
+pub const BASIC_AUTH_CHALLENGE: &str = r#"Basic realm="Simple File Store", charset="UTF-8""#;
 
 pub static KEYS: LazyLock<Keys> = LazyLock::new(|| Keys::new(CONFIG.secret.as_bytes()));
 
@@ -29,11 +45,192 @@ impl Keys {
     }
 }
 
+/// Check a username and password against the configured users, on a blocking
+/// thread since argon2 is slow on purpose.
+pub async fn verify_credentials(username: &str, password: &str) -> Result<bool, ServerError> {
+    let (username, password) = (username.to_string(), password.to_string());
+    Ok(spawn_blocking(move || CONFIG.users.verify(&username, &password)).await?)
+}
+
+/// How long successful Basic credentials are remembered, so clients sending
+/// them with every request, e.g. each chunk of an upload, don't pay for argon2
+/// every time.
+const BASIC_AUTH_CACHE_TTL: Duration = Duration::from_secs(60);
+
+static BASIC_AUTH_CACHE: LazyLock<CredentialCache> =
+    LazyLock::new(|| CredentialCache::new(BASIC_AUTH_CACHE_TTL));
+
+/// Recently verified credentials. Only a keyed hash of them is kept, with a key
+/// that changes on every start, so the cache doesn't hold any passwords.
+struct CredentialCache {
+    key: [u8; 32],
+    ttl: Duration,
+    verified: Mutex<HashMap<[u8; 32], Instant>>,
+}
+impl CredentialCache {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            key: rng().random(),
+            ttl,
+            verified: Mutex::default(),
+        }
+    }
+
+    fn digest(&self, username: &str, password: &str) -> [u8; 32] {
+        // Usernames can't contain a colon, so this is unambiguous.
+        blake3::keyed_hash(&self.key, format!("{username}:{password}").as_bytes()).into()
+    }
+
+    fn contains(&self, username: &str, password: &str) -> bool {
+        let digest = self.digest(username, password);
+        self.verified
+            .lock()
+            .unwrap()
+            .get(&digest)
+            .is_some_and(|expires| *expires > Instant::now())
+    }
+
+    fn insert(&self, username: &str, password: &str) {
+        let digest = self.digest(username, password);
+        let now = Instant::now();
+        let mut verified = self.verified.lock().unwrap();
+        verified.retain(|_, expires| *expires > now);
+        verified.insert(digest, now + self.ttl);
+    }
+}
+
+/// Whether the request comes from a browser, which gets the login page
+/// rather than a Basic authentication challenge when it isn't logged in.
+/// Browsers send `Sec-Fetch-Mode` with every request, and older ones at
+/// least ask for HTML when navigating.
+pub fn is_browser(headers: &HeaderMap) -> bool {
+    headers.contains_key("sec-fetch-mode")
+        || headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.contains("text/html"))
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub exp: u64,
+    /// Always the current role of the user, even for tokens issued before it
+    /// changed, see [`decode_token`].
+    #[serde(default)]
+    pub role: Role,
 }
+impl Claims {
+    /// Claims for `sub`, valid for the configured token expiry.
+    pub fn new(sub: String) -> Self {
+        Self {
+            role: CONFIG.users.role(&sub).unwrap_or(Role::Viewer),
+            sub,
+            exp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                + CONFIG.token_expiry,
+        }
+    }
+
+    pub fn can(&self, capability: Capability) -> bool {
+        self.role.can(capability)
+    }
+
+    /// Fail with `403 Forbidden` unless the user has `capability`.
+    pub fn require(&self, capability: Capability) -> Result<(), ServerError> {
+        if self.can(capability) {
+            Ok(())
+        } else {
+            Err(ServerError::Forbidden)
+        }
+    }
+
+    /// Whether the user may use `capability` on `path`, as returned by
+    /// `safe_join`: both their role and their access rules have to allow it.
+    pub fn can_at(&self, capability: Capability, path: &Path) -> bool {
+        self.can(capability) && self.check_rules(path, |acl, path| acl.allows(capability, path))
+    }
+
+    /// Like [`Claims::can_at`], for `path` and everything inside it.
+    pub fn can_at_tree(&self, capability: Capability, path: &Path) -> bool {
+        self.can(capability)
+            && self.check_rules(path, |acl, path| acl.allows_subtree(capability, path))
+    }
+
+    /// Whether the access rules let the user read or write somewhere below
+    /// `dir`, so they can browse through it even if they can't read it.
+    pub fn can_browse(&self, dir: &Path) -> bool {
+        [Capability::Read, Capability::Write]
+            .into_iter()
+            .any(|capability| {
+                self.can(capability)
+                    && self.check_rules(dir, |acl, dir| acl.allows_below(capability, dir))
+            })
+    }
+
+    /// The check every handler makes before acting on `path`: fail with
+    /// `403 Forbidden` unless the user may use `capability` on it.
+    pub fn authorize(&self, capability: Capability, path: &Path) -> Result<(), ServerError> {
+        if self.can_at(capability, path) {
+            Ok(())
+        } else {
+            Err(ServerError::Forbidden)
+        }
+    }
+
+    /// Like [`Claims::authorize`], for `path` and everything inside it.
+    pub fn authorize_tree(&self, capability: Capability, path: &Path) -> Result<(), ServerError> {
+        if self.can_at_tree(capability, path) {
+            Ok(())
+        } else {
+            Err(ServerError::Forbidden)
+        }
+    }
+
+    /// Run `check` with the access rules of the user and `path` relative to
+    /// the store root. Paths outside of the store are never allowed.
+    fn check_rules(&self, path: &Path, check: impl FnOnce(&Acl, &[&str]) -> bool) -> bool {
+        let Some(acl) = CONFIG.users.acl(&self.sub) else {
+            return false;
+        };
+        relative_components(path, &CONFIG.store_path).is_some_and(|path| check(acl, &path))
+    }
+
+    /// The directory the user is confined to, which every path they send is
+    /// relative to.
+    pub fn root(&self) -> &'static Path {
+        CONFIG
+            .users
+            .root(&self.sub)
+            .expect("claims are only issued to configured users")
+    }
+}
+
+/// Decode a token, which is only valid as long as its user still exists, so
+/// removing someone from the users file revokes their tokens on restart.
+/// Changing their role likewise applies to the tokens they already have.
+fn decode_token(token: &str, parts: &Parts) -> Result<Claims, ServerError> {
+    decode::<Claims>(token, &KEYS.decoding, &Validation::default())
+        .ok()
+        .and_then(|token_data| {
+            let role = CONFIG.users.role(&token_data.claims.sub)?;
+            Some(Claims {
+                role,
+                ..token_data.claims
+            })
+        })
+        .ok_or_else(|| ServerError::InvalidToken {
+            current_uri: parts
+                .uri
+                .path_and_query()
+                .map(|p_and_q| encode_uri(p_and_q.as_str().as_bytes()).collect())
+                .unwrap_or("/".to_string()),
+        })
+}
+
 impl<S> FromRequestParts<S> for Claims
 where
     S: Send + Sync,
@@ -42,38 +239,37 @@ where
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
         // Extract the token from the authorization header or cookie,
-        // header has higher priority.
+        // header has higher priority. Clients that can't obtain a token may
+        // send Basic credentials instead, which are checked on every request.
 
         if let Ok(TypedHeader(Authorization(bearer))) =
             parts.extract::<TypedHeader<Authorization<Bearer>>>().await
         {
-            let token_data =
-                decode::<Claims>(bearer.token(), &KEYS.decoding, &Validation::default()).map_err(
-                    |_| ServerError::InvalidToken {
-                        current_uri: parts
-                            .uri
-                            .path_and_query()
-                            .map(|p_and_q| encode_uri(p_and_q.as_str().as_bytes()).collect())
-                            .unwrap_or("/".to_string()),
-                    },
-                )?;
-
-            return Ok(token_data.claims);
+            return decode_token(bearer.token(), parts);
+        }
+
+        if let Ok(TypedHeader(Authorization(basic))) =
+            parts.extract::<TypedHeader<Authorization<Basic>>>().await
+        {
+            let (username, password) = (basic.username(), basic.password());
+            if !BASIC_AUTH_CACHE.contains(username, password) {
+                if !verify_credentials(username, password).await? {
+                    return Err(ServerError::InvalidBasicCredentials);
+                }
+                BASIC_AUTH_CACHE.insert(username, password);
+            }
+
+            return Ok(Claims::new(basic.username().to_string()));
         }
 
         if let Ok(TypedHeader(cookie)) = parts.extract::<TypedHeader<Cookie>>().await
             && let Some(token) = cookie.get("access_token")
         {
-            let token_data = decode::<Claims>(token, &KEYS.decoding, &Validation::default())
-                .map_err(|_| ServerError::InvalidToken {
-                    current_uri: parts
-                        .uri
-                        .path_and_query()
-                        .map(|p_and_q| encode_uri(p_and_q.as_str().as_bytes()).collect())
-                        .unwrap_or("/".to_string()),
-                })?;
-
-            return Ok(token_data.claims);
+            return decode_token(token, parts);
+        }
+
+        if !is_browser(&parts.headers) {
+            return Err(ServerError::BasicAuthRequired);
         }
 
         Err(ServerError::MissingCredentials {
@@ -96,7 +292,9 @@ where
         state: &S,
     ) -> Result<Option<Self>, Self::Rejection> {
         match <Claims as FromRequestParts<_>>::from_request_parts(parts, state).await {
-            Err(ServerError::MissingCredentials { .. }) => Ok(None),
+            Err(ServerError::MissingCredentials { .. } | ServerError::BasicAuthRequired) => {
+                Ok(None)
+            }
 
             Err(e) => Err(e),
 
@@ -104,3 +302,44 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn recognises_browsers() {
+        assert!(is_browser(&headers(&[("sec-fetch-mode", "navigate")])));
+        assert!(is_browser(&headers(&[(
+            "accept",
+            "text/html,application/xhtml+xml,*/*;q=0.8"
+        )])));
+
+        // curl, wget and most download managers.
+        assert!(!is_browser(&headers(&[("accept", "*/*")])));
+        assert!(!is_browser(&headers(&[])));
+        assert!(!is_browser(&headers(&[("accept", "application/json")])));
+    }
+
+    #[test]
+    fn caches_verified_credentials_until_they_expire() {
+        let cache = CredentialCache::new(Duration::from_secs(60));
+        cache.insert("alice", "secret");
+
+        assert!(cache.contains("alice", "secret"));
+        assert!(!cache.contains("alice", "other"));
+        assert!(!cache.contains("bob", "secret"));
+
+        let expired = CredentialCache::new(Duration::ZERO);
+        expired.insert("alice", "secret");
+        assert!(!expired.contains("alice", "secret"));
+    }
+}
@@ -0,0 +1,186 @@
+use std::ops::Range;
+
+use crate::upload::ChunkStatus;
+
+const CHUNKS_PER_BYTE: usize = 4;
+
+/// The status of every chunk of a resumable upload, packed two bits per chunk.
+/// Its size is fixed by the number of chunks, so a status change rewrites a
+/// single byte of the file it is stored in rather than the whole meta.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChunkMap {
+    len: usize,
+    bytes: Vec<u8>,
+}
+impl ChunkMap {
+    /// A map of `len` chunks, all `NotStarted`.
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            bytes: vec![0; len.div_ceil(CHUNKS_PER_BYTE)],
+        }
+    }
+
+    /// Take the map of `len` chunks stored in `bytes`. `None` unless `bytes`
+    /// has the size of such a map and only valid statuses.
+    pub fn from_bytes(len: usize, bytes: Vec<u8>) -> Option<Self> {
+        if bytes.len() != len.div_ceil(CHUNKS_PER_BYTE) {
+            return None;
+        }
+        let map = Self { len, bytes };
+        // Padding bits past the last chunk have to be zero, so equal maps
+        // always have equal bytes.
+        let valid = (0..map.bytes.len() * CHUNKS_PER_BYTE).all(|index| {
+            if index < len {
+                map.bits(index) <= ChunkStatus::Completed as u8
+            } else {
+                map.bits(index) == 0
+            }
+        });
+        valid.then_some(map)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<ChunkStatus> {
+        (index < self.len).then(|| match self.bits(index) {
+            0 => ChunkStatus::NotStarted,
+            1 => ChunkStatus::Ongoing,
+            _ => ChunkStatus::Completed,
+        })
+    }
+
+    /// Set the status of chunk `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no such chunk.
+    pub fn set(&mut self, index: usize, status: ChunkStatus) {
+        assert!(index < self.len, "chunk {index} out of {}", self.len);
+        let shift = (index % CHUNKS_PER_BYTE) * 2;
+        let byte = &mut self.bytes[index / CHUNKS_PER_BYTE];
+        *byte = (*byte & !(0b11 << shift)) | ((status as u8) << shift);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ChunkStatus> + '_ {
+        (0..self.len).map(|index| self.get(index).unwrap())
+    }
+
+    /// The ranges of consecutive chunks that are not completed yet.
+    pub fn missing_ranges(&self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for (index, status) in self.iter().enumerate() {
+            if status.is_completed() {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(range) if range.end == index => range.end += 1,
+                _ => ranges.push(index..index + 1),
+            }
+        }
+        ranges
+    }
+
+    /// Offsets of the bytes that differ from `other`, a map of the same size.
+    pub fn changed_bytes<'a>(&'a self, other: &'a Self) -> impl Iterator<Item = usize> + 'a {
+        self.bytes
+            .iter()
+            .zip(&other.bytes)
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(offset, _)| offset)
+    }
+
+    fn bits(&self, index: usize) -> u8 {
+        (self.bytes[index / CHUNKS_PER_BYTE] >> ((index % CHUNKS_PER_BYTE) * 2)) & 0b11
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ChunkStatus::*;
+
+    #[test]
+    fn packs_four_chunks_per_byte() {
+        assert_eq!(ChunkMap::new(0).as_bytes().len(), 0);
+        assert_eq!(ChunkMap::new(4).as_bytes().len(), 1);
+        assert_eq!(ChunkMap::new(5).as_bytes().len(), 2);
+
+        let mut map = ChunkMap::new(5);
+        map.set(1, Ongoing);
+        map.set(4, Completed);
+        assert_eq!(map.as_bytes(), [0b0000_0100, 0b0000_0010]);
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            [NotStarted, Ongoing, NotStarted, NotStarted, Completed]
+        );
+        assert_eq!(map.get(5), None);
+
+        map.set(1, NotStarted);
+        assert_eq!(map.as_bytes(), [0, 0b0000_0010]);
+    }
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let mut map = ChunkMap::new(6);
+        map.set(0, Completed);
+        map.set(5, Ongoing);
+
+        assert_eq!(
+            ChunkMap::from_bytes(6, map.as_bytes().to_vec()),
+            Some(map.clone())
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_bytes() {
+        // Wrong size.
+        assert_eq!(ChunkMap::from_bytes(5, vec![0]), None);
+        // Status 3 doesn't exist.
+        assert_eq!(ChunkMap::from_bytes(4, vec![0b1100_0000]), None);
+        // Bits set past the last chunk.
+        assert_eq!(ChunkMap::from_bytes(3, vec![0b0100_0000]), None);
+    }
+
+    #[test]
+    fn missing_ranges_merge_consecutive_chunks() {
+        let mut map = ChunkMap::new(7);
+        assert_eq!(
+            map.missing_ranges(),
+            std::iter::once(0..7).collect::<Vec<_>>()
+        );
+
+        for index in [0, 3, 4] {
+            map.set(index, Completed);
+        }
+        map.set(5, Ongoing);
+        assert_eq!(map.missing_ranges(), [1..3, 5..7]);
+
+        for index in 0..7 {
+            map.set(index, Completed);
+        }
+        assert!(map.missing_ranges().is_empty());
+    }
+
+    #[test]
+    fn reports_changed_bytes() {
+        let before = ChunkMap::new(12);
+        let mut after = before.clone();
+        after.set(1, Completed);
+        after.set(2, Ongoing);
+        after.set(9, Completed);
+
+        assert_eq!(after.changed_bytes(&before).collect::<Vec<_>>(), [0, 2]);
+    }
+}
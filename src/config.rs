@@ -9,6 +9,9 @@ use rand::rng;
 use tokio_rustls::rustls::ServerConfig as TlsServerConfig;
 use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
 
+use crate::conflict::ConflictPolicy;
+use crate::users::{self, Users};
+
 const VERSION: &str = match std::option_env!("CARGO_PKG_VERSION") {
     Some(version) => version,
     None => "unknown",
@@ -20,7 +23,7 @@ pub static CONFIG: LazyLock<Config> = LazyLock::new(|| {
     match cfg {
         Ok(c) => c,
         Err(err) => {
-            eprintln!("Error loading configuration: {err}");
+            eprintln!("Error loading configuration: {err:#}");
             std::process::exit(1);
         }
     }
@@ -34,11 +37,23 @@ pub struct Config {
     pub listen: SocketAddr,
     pub store_path: PathBuf,
     pub chunk_size: usize,
+    /// Bounds for the chunk size clients may request for a resumable upload,
+    /// `chunk_size` is used when they don't request one.
+    pub min_chunk_size: usize,
+    pub max_chunk_size: usize,
     pub max_active_upload_chunks: usize,
     pub max_active_chunks_per_upload: usize,
     pub max_active_upload_bytes: usize,
-    pub username: String,
-    pub password: String,
+    pub upload_conflict: ConflictPolicy,
+    /// Seconds without chunk activity after which a resumable upload is
+    /// removed, `0` keeps abandoned uploads forever.
+    pub upload_ttl: u64,
+    /// Remove expired uploads and share links and exit instead of starting
+    /// the server.
+    pub sweep_uploads: bool,
+    /// Either read from the users file, or the single user given by the
+    /// username and password options.
+    pub users: Users,
     pub secret: String,
     pub token_expiry: u64,
     pub tls: Option<Arc<TlsServerConfig>>,
@@ -48,12 +63,17 @@ impl Default for Config {
         Config {
             listen: SocketAddr::V6("[::]:8080".parse().unwrap()),
             store_path: current_dir().unwrap(),
-            chunk_size: 1024 * 1024 * 8, // 8MB
+            chunk_size: 1024 * 1024 * 8,      // 8MB
+            min_chunk_size: 1024 * 1024,      // 1MB
+            max_chunk_size: 1024 * 1024 * 64, // 64MB
             max_active_upload_chunks: 32,
             max_active_chunks_per_upload: 6,
             max_active_upload_bytes: 1024 * 1024 * 512, // 512MB
-            username: "admin".to_string(),
-            password: "password".to_string(),
+            upload_conflict: ConflictPolicy::Reject,
+            upload_ttl: 60 * 60 * 24 * 7, // 7 days
+            sweep_uploads: false,
+            // Only hashed once the options show the default user is needed.
+            users: Users::default(),
             secret: Alphanumeric.sample_string(&mut rng(), 16),
             token_expiry: 60 * 60 * 24, // 24 hours
             tls: None,
@@ -124,12 +144,78 @@ impl Config {
             bail!("Max active upload bytes must be greater than or equal to chunk size");
         }
 
-        if let Some(username) = user_config.username {
-            config.username = username;
+        // Unless set, the bounds widen to include the chunk size and narrow to
+        // what the byte budget can hold.
+        match user_config.min_chunk_size {
+            Some(min_chunk_size_string) => {
+                config.min_chunk_size = min_chunk_size_string
+                    .parse()
+                    .context("Invalid min chunk size")?;
+            }
+            None => config.min_chunk_size = config.min_chunk_size.min(config.chunk_size),
+        }
+        if config.min_chunk_size == 0 {
+            bail!("Min chunk size must be greater than 0");
+        }
+        if config.min_chunk_size > config.chunk_size {
+            bail!("Min chunk size must be less than or equal to chunk size");
+        }
+        match user_config.max_chunk_size {
+            Some(max_chunk_size_string) => {
+                config.max_chunk_size = max_chunk_size_string
+                    .parse()
+                    .context("Invalid max chunk size")?;
+            }
+            None => {
+                config.max_chunk_size = config
+                    .max_chunk_size
+                    .max(config.chunk_size)
+                    .min(config.max_active_upload_bytes)
+            }
+        }
+        if config.max_chunk_size < config.chunk_size {
+            bail!("Max chunk size must be greater than or equal to chunk size");
+        }
+        if config.max_active_upload_bytes < config.max_chunk_size {
+            bail!("Max active upload bytes must be greater than or equal to max chunk size");
+        }
+
+        if let Some(upload_conflict_string) = user_config.upload_conflict {
+            config.upload_conflict = upload_conflict_string
+                .parse()
+                .context("Invalid upload conflict policy")?;
+        }
+
+        if let Some(upload_ttl_string) = user_config.upload_ttl {
+            config.upload_ttl = upload_ttl_string.parse().context("Invalid upload TTL")?;
+        }
+
+        config.sweep_uploads = user_config.sweep_uploads;
+        if config.sweep_uploads && config.upload_ttl == 0 {
+            bail!("--sweep-uploads requires an upload TTL greater than 0");
         }
 
-        if let Some(password) = user_config.password {
-            config.password = password;
+        match user_config.users_file {
+            Some(users_file) => {
+                if user_config.username.is_some() || user_config.password.is_some() {
+                    bail!("The users file can't be combined with a username or password");
+                }
+                config.users = Users::from_file(
+                    &current_dir()?.join(users_file),
+                    &config.store_path,
+                    user_config.user_homes,
+                )?;
+            }
+            None => {
+                if user_config.user_homes {
+                    bail!("--user-homes requires a users file");
+                }
+                config.users = Users::single(
+                    user_config.username.unwrap_or_else(|| "admin".to_string()),
+                    user_config.password.as_deref().unwrap_or("password"),
+                    &config.store_path,
+                )?;
+            }
         }
 
         if let Some(secret) = user_config.secret {
@@ -181,9 +267,16 @@ pub struct UserConfig {
     listen: Option<String>,
     store_path: Option<String>,
     chunk_size: Option<String>,
+    min_chunk_size: Option<String>,
+    max_chunk_size: Option<String>,
     max_active_upload_chunks: Option<String>,
     max_active_chunks_per_upload: Option<String>,
     max_active_upload_bytes: Option<String>,
+    upload_conflict: Option<String>,
+    upload_ttl: Option<String>,
+    sweep_uploads: bool,
+    users_file: Option<String>,
+    user_homes: bool,
     username: Option<String>,
     password: Option<String>,
     secret: Option<String>,
@@ -208,6 +301,14 @@ impl UserConfig {
             config.chunk_size = Some(chunk_size);
         }
 
+        if let Ok(min_chunk_size) = std::env::var("SFS_MIN_CHUNK_SIZE") {
+            config.min_chunk_size = Some(min_chunk_size);
+        }
+
+        if let Ok(max_chunk_size) = std::env::var("SFS_MAX_CHUNK_SIZE") {
+            config.max_chunk_size = Some(max_chunk_size);
+        }
+
         if let Ok(max_active_upload_chunks) = std::env::var("SFS_MAX_ACTIVE_UPLOAD_CHUNKS") {
             config.max_active_upload_chunks = Some(max_active_upload_chunks);
         }
@@ -221,6 +322,22 @@ impl UserConfig {
             config.max_active_upload_bytes = Some(max_active_upload_bytes);
         }
 
+        if let Ok(upload_conflict) = std::env::var("SFS_UPLOAD_CONFLICT") {
+            config.upload_conflict = Some(upload_conflict);
+        }
+
+        if let Ok(upload_ttl) = std::env::var("SFS_UPLOAD_TTL") {
+            config.upload_ttl = Some(upload_ttl);
+        }
+
+        if let Ok(users_file) = std::env::var("SFS_USERS_FILE") {
+            config.users_file = Some(users_file);
+        }
+
+        if let Ok(user_homes) = std::env::var("SFS_USER_HOMES") {
+            config.user_homes = matches!(user_homes.as_str(), "1" | "true");
+        }
+
         if let Ok(username) = std::env::var("SFS_USERNAME") {
             config.username = Some(username);
         }
@@ -249,7 +366,7 @@ impl UserConfig {
     }
 
     /// Get the configuration from the command line arguments.
-    /// Also handles the `--help` and the `--version` argument,
+    /// Also handles the `--help`, `--version` and `--hash-password` arguments,
     /// which would exits the process immediately.
     pub fn from_cli() -> Result<Self> {
         let mut config = UserConfig::default();
@@ -264,9 +381,17 @@ impl UserConfig {
                         --listen, -l <ADDR>\t\tListen address (default: [::]:8080)\n\
                         --store-path, -p <PATH>\t\tPath to store files (default: current directory)\n\
                         --chunk-size, -s <SIZE>\t\tChunk size in bytes (default: 8MB)\n\
+                        --min-chunk-size <SIZE>\t\tSmallest chunk size clients may request (default: 1MB)\n\
+                        --max-chunk-size <SIZE>\t\tLargest chunk size clients may request (default: 64MB)\n\
                         --max-active-upload-chunks <COUNT>\tMaximum active resumable upload chunks across the server (default: 32)\n\
                         --max-active-chunks-per-upload <COUNT>\tMaximum active resumable upload chunks per file (default: 6)\n\
                         --max-active-upload-bytes <BYTES>\tActive resumable upload byte budget (default: 512MB)\n\
+                        --upload-conflict <POLICY>\tWhat uploads do when the file exists: reject, overwrite or auto-rename (default: reject)\n\
+                        --upload-ttl <SECONDS>\tRemove resumable uploads idle for this long, 0 to keep them (default: 7 days)\n\
+                        --sweep-uploads\t\tRemove expired resumable uploads and share links and exit\n\
+                        --users-file <PATH>\t\tTOML file of users and their password hashes\n\
+                        --user-homes\t\tConfine users without a root to a directory named after them\n\
+                        --hash-password\t\tRead a password from stdin, print its hash for the users file and exit\n\
                         --username, -u <USERNAME>\tUsername for authentication (default: admin)\n\
                         --password, -w <PASSWORD>\tPassword for authentication (default: password)\n\
                         --secret, -x <SECRET>\t\tSecret for JWT (default: random 16 characters)\n\
@@ -279,9 +404,15 @@ impl UserConfig {
                         SFS_LISTEN\t\tListen address\n\
                         SFS_STORE_PATH\t\tPath to store files\n\
                         SFS_CHUNK_SIZE\t\tChunk size in bytes\n\
+                        SFS_MIN_CHUNK_SIZE\tSmallest chunk size clients may request\n\
+                        SFS_MAX_CHUNK_SIZE\tLargest chunk size clients may request\n\
                         SFS_MAX_ACTIVE_UPLOAD_CHUNKS\tMaximum active resumable upload chunks across the server\n\
                         SFS_MAX_ACTIVE_CHUNKS_PER_UPLOAD\tMaximum active resumable upload chunks per file\n\
                         SFS_MAX_ACTIVE_UPLOAD_BYTES\tActive resumable upload byte budget\n\
+                        SFS_UPLOAD_CONFLICT\tWhat uploads do when the file exists\n\
+                        SFS_UPLOAD_TTL\t\tRemove resumable uploads idle for this many seconds\n\
+                        SFS_USERS_FILE\t\tTOML file of users and their password hashes\n\
+                        SFS_USER_HOMES\t\tConfine users without a root to a directory named after them\n\
                         SFS_USERNAME\t\tUsername for authentication\n\
                         SFS_PASSWORD\t\tPassword for authentication\n\
                         SFS_SECRET\t\tSecret for JWT\n\
@@ -297,6 +428,12 @@ impl UserConfig {
                     std::process::exit(0);
                 }
 
+                "--hash-password" => {
+                    let password = users::read_password()?;
+                    println!("{}", users::hash_password(&password)?);
+                    std::process::exit(0);
+                }
+
                 "--listen" | "-l" => {
                     if config.listen.is_some() {
                         bail!("--listen can only be specified once");
@@ -319,6 +456,20 @@ impl UserConfig {
                     config.chunk_size = Some(chunk_size);
                 }
 
+                "--min-chunk-size" => {
+                    let min_chunk_size = args
+                        .next()
+                        .context("--min-chunk-size requires an argument")?;
+                    config.min_chunk_size = Some(min_chunk_size);
+                }
+
+                "--max-chunk-size" => {
+                    let max_chunk_size = args
+                        .next()
+                        .context("--max-chunk-size requires an argument")?;
+                    config.max_chunk_size = Some(max_chunk_size);
+                }
+
                 "--max-active-upload-chunks" => {
                     let max_active_upload_chunks = args
                         .next()
@@ -340,6 +491,31 @@ impl UserConfig {
                     config.max_active_upload_bytes = Some(max_active_upload_bytes);
                 }
 
+                "--upload-conflict" => {
+                    let upload_conflict = args
+                        .next()
+                        .context("--upload-conflict requires an argument")?;
+                    config.upload_conflict = Some(upload_conflict);
+                }
+
+                "--upload-ttl" => {
+                    let upload_ttl = args.next().context("--upload-ttl requires an argument")?;
+                    config.upload_ttl = Some(upload_ttl);
+                }
+
+                "--sweep-uploads" => {
+                    config.sweep_uploads = true;
+                }
+
+                "--users-file" => {
+                    let users_file = args.next().context("--users-file requires an argument")?;
+                    config.users_file = Some(users_file);
+                }
+
+                "--user-homes" => {
+                    config.user_homes = true;
+                }
+
                 "--username" | "-u" => {
                     let username = args.next().context("--username/-u requires an argument")?;
                     config.username = Some(username);
@@ -395,6 +571,14 @@ impl UserConfig {
             config.chunk_size = Some(chunk_size);
         }
 
+        if let Some(min_chunk_size) = cli_config.min_chunk_size {
+            config.min_chunk_size = Some(min_chunk_size);
+        }
+
+        if let Some(max_chunk_size) = cli_config.max_chunk_size {
+            config.max_chunk_size = Some(max_chunk_size);
+        }
+
         if let Some(max_active_upload_chunks) = cli_config.max_active_upload_chunks {
             config.max_active_upload_chunks = Some(max_active_upload_chunks);
         }
@@ -407,6 +591,22 @@ impl UserConfig {
             config.max_active_upload_bytes = Some(max_active_upload_bytes);
         }
 
+        if let Some(upload_conflict) = cli_config.upload_conflict {
+            config.upload_conflict = Some(upload_conflict);
+        }
+
+        if let Some(upload_ttl) = cli_config.upload_ttl {
+            config.upload_ttl = Some(upload_ttl);
+        }
+
+        config.sweep_uploads = cli_config.sweep_uploads;
+
+        if let Some(users_file) = cli_config.users_file {
+            config.users_file = Some(users_file);
+        }
+
+        config.user_homes |= cli_config.user_homes;
+
         if let Some(username) = cli_config.username {
             config.username = Some(username);
         }
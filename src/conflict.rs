@@ -0,0 +1,209 @@
+use std::io::ErrorKind as IoErrorKind;
+use std::path::{Path as StdPath, PathBuf};
+use std::str::FromStr;
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::errors::ServerError;
+use crate::fs_utils::rename_no_replace;
+
+/// How many numbered names `auto-rename` tries before giving up.
+const MAX_AUTO_RENAME_ATTEMPTS: usize = 10_000;
+
+/// What an upload does when its target path is already taken.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    /// Refuse the upload and keep the existing file.
+    #[default]
+    Reject,
+
+    /// Replace the existing file.
+    Overwrite,
+
+    /// Keep both, storing the upload as e.g. `report (1).pdf`.
+    AutoRename,
+}
+impl ConflictPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Reject => "reject",
+            Self::Overwrite => "overwrite",
+            Self::AutoRename => "auto-rename",
+        }
+    }
+}
+impl FromStr for ConflictPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "overwrite" => Ok(Self::Overwrite),
+            "auto-rename" => Ok(Self::AutoRename),
+            _ => bail!("expected one of `reject`, `overwrite` or `auto-rename`, got `{s}`"),
+        }
+    }
+}
+
+/// Move a finished upload from `from` to `to` according to `policy`, and
+/// return the path it ended up at. The no-replace renames make the existence
+/// check and the move a single step, so two uploads racing for the same name
+/// can't both win.
+pub async fn place(
+    from: PathBuf,
+    to: PathBuf,
+    policy: ConflictPolicy,
+) -> Result<PathBuf, ServerError> {
+    match policy {
+        ConflictPolicy::Overwrite => {
+            fs::rename(&from, &to).await?;
+            Ok(to)
+        }
+
+        ConflictPolicy::Reject => match rename_no_replace(from, to.clone()).await {
+            Ok(()) => Ok(to),
+            Err(err) if err.kind() == IoErrorKind::AlreadyExists => Err(ServerError::FileConflict),
+            Err(err) => Err(err.into()),
+        },
+
+        ConflictPolicy::AutoRename => {
+            for attempt in 0..MAX_AUTO_RENAME_ATTEMPTS {
+                let candidate = numbered_path(&to, attempt);
+                match rename_no_replace(from.clone(), candidate.clone()).await {
+                    Ok(()) => return Ok(candidate),
+                    Err(err) if err.kind() == IoErrorKind::AlreadyExists => continue,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Err(ServerError::FileConflict)
+        }
+    }
+}
+
+/// `path` itself for `n == 0`, otherwise `path` with ` (n)` inserted before
+/// the extension: `report.pdf` becomes `report (1).pdf`, `.env` becomes `.env (1)`.
+fn numbered_path(path: &StdPath, n: usize) -> PathBuf {
+    if n == 0 {
+        return path.to_path_buf();
+    }
+
+    // Targets are joined from request paths and form fields, which are UTF-8,
+    // so their names are too.
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let file_name = match path.extension().and_then(|s| s.to_str()) {
+        Some(extension) => format!("{stem} ({n}).{extension}"),
+        None => format!("{stem} ({n})"),
+    };
+    path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn numbered_path_inserts_counter_before_extension() {
+        let path = PathBuf::from("/store/report.pdf");
+        assert_eq!(numbered_path(&path, 0), path);
+        assert_eq!(
+            numbered_path(&path, 1),
+            PathBuf::from("/store/report (1).pdf")
+        );
+        assert_eq!(
+            numbered_path(&PathBuf::from("/store/README"), 2),
+            PathBuf::from("/store/README (2)")
+        );
+        assert_eq!(
+            numbered_path(&PathBuf::from("/store/.env"), 1),
+            PathBuf::from("/store/.env (1)")
+        );
+    }
+
+    #[test]
+    fn parses_policy_names() {
+        assert_eq!(
+            "reject".parse::<ConflictPolicy>().unwrap(),
+            ConflictPolicy::Reject
+        );
+        assert_eq!(
+            "overwrite".parse::<ConflictPolicy>().unwrap(),
+            ConflictPolicy::Overwrite
+        );
+        assert_eq!(
+            "auto-rename".parse::<ConflictPolicy>().unwrap(),
+            ConflictPolicy::AutoRename
+        );
+        assert!("rename".parse::<ConflictPolicy>().is_err());
+    }
+
+    fn fixture() -> (TempDir, PathBuf, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let from = dir.path().join("upload.tmp");
+        let to = dir.path().join("report.pdf");
+        std::fs::write(&from, b"new").unwrap();
+        std::fs::write(&to, b"old").unwrap();
+        (dir, from, to)
+    }
+
+    #[tokio::test]
+    async fn reject_keeps_existing_file() {
+        let (_dir, from, to) = fixture();
+
+        let result = place(from.clone(), to.clone(), ConflictPolicy::Reject).await;
+
+        assert!(matches!(result, Err(ServerError::FileConflict)));
+        assert_eq!(std::fs::read(&to).unwrap(), b"old");
+        assert!(from.exists());
+    }
+
+    #[tokio::test]
+    async fn overwrite_replaces_existing_file() {
+        let (_dir, from, to) = fixture();
+
+        let placed = place(from.clone(), to.clone(), ConflictPolicy::Overwrite)
+            .await
+            .unwrap();
+
+        assert_eq!(placed, to);
+        assert_eq!(std::fs::read(&to).unwrap(), b"new");
+    }
+
+    #[tokio::test]
+    async fn auto_rename_picks_next_free_name() {
+        let (dir, from, to) = fixture();
+        std::fs::write(dir.path().join("report (1).pdf"), b"older").unwrap();
+
+        let placed = place(from.clone(), to.clone(), ConflictPolicy::AutoRename)
+            .await
+            .unwrap();
+
+        assert_eq!(placed, dir.path().join("report (2).pdf"));
+        assert_eq!(std::fs::read(&placed).unwrap(), b"new");
+        assert_eq!(std::fs::read(&to).unwrap(), b"old");
+    }
+
+    #[tokio::test]
+    async fn every_policy_places_missing_target_as_is() {
+        for policy in [
+            ConflictPolicy::Reject,
+            ConflictPolicy::Overwrite,
+            ConflictPolicy::AutoRename,
+        ] {
+            let dir = TempDir::new().unwrap();
+            let from = dir.path().join("upload.tmp");
+            let to = dir.path().join("report.pdf");
+            std::fs::write(&from, b"new").unwrap();
+
+            let placed = place(from, to.clone(), policy).await.unwrap();
+
+            assert_eq!(placed, to);
+        }
+    }
+}
@@ -1,26 +1,54 @@
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 
-use axum::extract::multipart::MultipartError;
+use axum::extract::multipart::{MultipartError, MultipartRejection};
+use axum::extract::rejection::{FormRejection, JsonRejection};
 use axum::http::StatusCode;
+use axum::http::header::WWW_AUTHENTICATE;
 use axum::response::{Html, IntoResponse, Json, Response};
 use rustix::io::Errno;
 use sailfish::{RenderError, TemplateOnce};
 use serde_json::json;
 use tokio::task::JoinError;
 
+use crate::auth::BASIC_AUTH_CHALLENGE;
 use crate::html;
 use crate::templates;
 
 #[derive(Debug)]
 pub enum ServerError {
-    MissingCredentials { current_uri: String },
-    TokenCreation { redirect_uri: Option<String> },
-    InvalidToken { current_uri: String },
+    MissingCredentials {
+        current_uri: String,
+    },
+    /// Like `MissingCredentials`, for clients that aren't browsers.
+    BasicAuthRequired,
+    InvalidBasicCredentials,
+    /// Wrong credentials for `POST /api/token`.
+    InvalidCredentials,
+    TokenCreation {
+        redirect_uri: Option<String>,
+    },
+    InvalidToken {
+        current_uri: String,
+    },
+    /// The user's role or access rules don't allow the request.
+    Forbidden,
+    /// The share link doesn't exist, has expired or was used up.
+    ShareNotFound,
+    /// A share link that would never work, e.g. one expiring right away.
+    InvalidShareOptions,
     IoError(IoError),
     InternalError(String),
     InvalidUploadForm,
     InvalidPath,
+    CannotModifyStoreRoot,
+    DirectoryIsNotEmpty,
+    DestinationAlreadyExists,
+    CannotMoveIntoItself,
+    CannotReplaceParent,
+    DestinationTypeMismatch,
+    DirectoryAlreadyExists,
     FileAlreadyExists,
+    FileConflict,
     FileIsNotCreated,
     UploadMetaIsBroken,
     MissingContentLength,
@@ -29,9 +57,30 @@ pub enum ServerError {
     InvalidChunkIndex,
     ChunkIsOngoing,
     ChunkIsCompleted,
+    UploadHasOngoingChunks,
     TooManyUploadRequests,
     UploadChunkFailed,
-    Custom { status: StatusCode, message: String },
+    InvalidChunkDigest,
+    ChunkDigestMismatch,
+    FileHashMismatch,
+    NotAChunkedUpload,
+    NotAnAppendableUpload,
+    UploadIsBusy,
+    UploadOffsetMismatch,
+    MissingUploadOffset,
+    InvalidUploadOffset,
+    MissingUploadLength,
+    InvalidUploadLength,
+    InvalidUploadMetadata,
+    InvalidUploadChecksum,
+    UnsupportedChecksumAlgorithm,
+    UploadChecksumMismatch,
+    InvalidUploadContentType,
+    UnsupportedTusVersion,
+    Custom {
+        status: StatusCode,
+        message: String,
+    },
 }
 impl IntoResponse for ServerError {
     fn into_response(self) -> Response {
@@ -104,6 +153,28 @@ impl IntoResponse for ServerError {
             )
                 .into_response(),
 
+            Self::Forbidden => (
+                StatusCode::FORBIDDEN,
+                Html(html::error::forbidden(
+                    "Your account is not allowed to do this.",
+                )),
+            )
+                .into_response(),
+
+            Self::ShareNotFound => (
+                StatusCode::NOT_FOUND,
+                Html(html::error::share_not_found()),
+            )
+                .into_response(),
+
+            Self::InvalidShareOptions => (
+                StatusCode::BAD_REQUEST,
+                Html(html::error::bad_request(
+                    "A share link has to last at least a second and allow at least one download.",
+                )),
+            )
+                .into_response(),
+
             Self::IoError(io_error) => match io_error.kind() {
                 IoErrorKind::NotFound => (
                     StatusCode::NOT_FOUND,
@@ -135,12 +206,74 @@ impl IntoResponse for ServerError {
             )
                 .into_response(),
 
+            Self::CannotModifyStoreRoot => (
+                StatusCode::BAD_REQUEST,
+                Html(html::error::bad_request(
+                    "The root directory of the store cannot be modified.",
+                )),
+            )
+                .into_response(),
+
+            Self::DirectoryIsNotEmpty => (
+                StatusCode::CONFLICT,
+                Html(html::error::conflict(
+                    "The directory is not empty. Delete it recursively to remove everything inside it.",
+                )),
+            )
+                .into_response(),
+
+            Self::DestinationAlreadyExists => (
+                StatusCode::CONFLICT,
+                Html(html::error::conflict(
+                    "The destination already exists. Enable overwriting to replace it.",
+                )),
+            )
+                .into_response(),
+
+            Self::CannotMoveIntoItself => (
+                StatusCode::BAD_REQUEST,
+                Html(html::error::bad_request(
+                    "A directory cannot be moved into itself.",
+                )),
+            )
+                .into_response(),
+
+            Self::CannotReplaceParent => (
+                StatusCode::BAD_REQUEST,
+                Html(html::error::bad_request(
+                    "An entry cannot replace a directory it is inside of.",
+                )),
+            )
+                .into_response(),
+
+            Self::DestinationTypeMismatch => (
+                StatusCode::CONFLICT,
+                Html(html::error::conflict(
+                    "A file can only replace a file, and a directory only a directory.",
+                )),
+            )
+                .into_response(),
+
+            Self::DirectoryAlreadyExists => (
+                StatusCode::CONFLICT,
+                Html(html::error::conflict(
+                    "A file or directory with the same name already exists.",
+                )),
+            )
+                .into_response(),
+
             Self::FileAlreadyExists => (
                 StatusCode::BAD_REQUEST,
                 Json(json!({"error": "The file already exists."})),
             )
                 .into_response(),
 
+            Self::FileConflict => (
+                StatusCode::CONFLICT,
+                Json(json!({"error": "A file with the same name already exists."})),
+            )
+                .into_response(),
+
             Self::FileIsNotCreated => (
                 StatusCode::NOT_FOUND,
                 Json(json!({"error": "The file is not created."})),
@@ -189,6 +322,33 @@ impl IntoResponse for ServerError {
             )
                 .into_response(),
 
+            Self::UploadHasOngoingChunks => (
+                StatusCode::CONFLICT,
+                [("retry-after", "1")],
+                Json(json!({"error": "Some chunks of the upload are still being uploaded. Please retry later."})),
+            )
+                .into_response(),
+
+            Self::BasicAuthRequired => (
+                StatusCode::UNAUTHORIZED,
+                [(WWW_AUTHENTICATE, BASIC_AUTH_CHALLENGE)],
+                Json(json!({"error": "Missing credentials."})),
+            )
+                .into_response(),
+
+            Self::InvalidBasicCredentials => (
+                StatusCode::UNAUTHORIZED,
+                [(WWW_AUTHENTICATE, BASIC_AUTH_CHALLENGE)],
+                Json(json!({"error": "Incorrect username or password."})),
+            )
+                .into_response(),
+
+            Self::InvalidCredentials => (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"error": "Incorrect username or password."})),
+            )
+                .into_response(),
+
             Self::TooManyUploadRequests => (
                 StatusCode::TOO_MANY_REQUESTS,
                 [("retry-after", "1")],
@@ -202,6 +362,110 @@ impl IntoResponse for ServerError {
             )
                 .into_response(),
 
+            Self::InvalidChunkDigest => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "The chunk digest in the HTTP header is invalid."})),
+            )
+                .into_response(),
+
+            Self::ChunkDigestMismatch => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "The chunk does not match its digest, it was corrupted in transit."})),
+            )
+                .into_response(),
+
+            Self::FileHashMismatch => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "The uploaded file does not match its expected hash. It was set aside instead of stored, please upload it again."})),
+            )
+                .into_response(),
+
+            Self::NotAChunkedUpload => (
+                StatusCode::CONFLICT,
+                Json(json!({"error": "The upload was created through tus, continue it through tus."})),
+            )
+                .into_response(),
+
+            Self::NotAnAppendableUpload => (
+                StatusCode::NOT_FOUND,
+                Json(json!({"error": "The upload was not created through tus."})),
+            )
+                .into_response(),
+
+            Self::UploadIsBusy => (
+                StatusCode::LOCKED,
+                Json(json!({"error": "Another request is appending to the upload."})),
+            )
+                .into_response(),
+
+            Self::UploadOffsetMismatch => (
+                StatusCode::CONFLICT,
+                Json(json!({"error": "The upload offset does not match the offset of the upload."})),
+            )
+                .into_response(),
+
+            Self::MissingUploadOffset => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "Missing upload offset in the HTTP header."})),
+            )
+                .into_response(),
+
+            Self::InvalidUploadOffset => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "The upload offset in the HTTP header is invalid."})),
+            )
+                .into_response(),
+
+            Self::MissingUploadLength => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "Missing upload length in the HTTP header."})),
+            )
+                .into_response(),
+
+            Self::InvalidUploadLength => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "The upload length in the HTTP header is invalid."})),
+            )
+                .into_response(),
+
+            Self::InvalidUploadMetadata => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "The upload metadata in the HTTP header is invalid."})),
+            )
+                .into_response(),
+
+            Self::InvalidUploadChecksum => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "The upload checksum in the HTTP header is invalid."})),
+            )
+                .into_response(),
+
+            Self::UnsupportedChecksumAlgorithm => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "The upload checksum algorithm is not supported."})),
+            )
+                .into_response(),
+
+            // 460 is the status tus assigns to checksum mismatches.
+            Self::UploadChecksumMismatch => (
+                StatusCode::from_u16(460).unwrap(),
+                Json(json!({"error": "The data does not match its checksum, it was corrupted in transit."})),
+            )
+                .into_response(),
+
+            Self::InvalidUploadContentType => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                Json(json!({"error": "The content type must be `application/offset+octet-stream`."})),
+            )
+                .into_response(),
+
+            Self::UnsupportedTusVersion => (
+                StatusCode::PRECONDITION_FAILED,
+                [("tus-version", "1.0.0")],
+                Json(json!({"error": "Only version 1.0.0 of the tus protocol is supported."})),
+            )
+                .into_response(),
+
             Self::Custom { status, message } => (
                 status,
                 Html(
@@ -241,6 +505,30 @@ impl From<MultipartError> for ServerError {
         }
     }
 }
+impl From<MultipartRejection> for ServerError {
+    fn from(multipart_rejection: MultipartRejection) -> Self {
+        ServerError::Custom {
+            status: multipart_rejection.status(),
+            message: multipart_rejection.body_text(),
+        }
+    }
+}
+impl From<FormRejection> for ServerError {
+    fn from(form_rejection: FormRejection) -> Self {
+        ServerError::Custom {
+            status: form_rejection.status(),
+            message: form_rejection.body_text(),
+        }
+    }
+}
+impl From<JsonRejection> for ServerError {
+    fn from(json_rejection: JsonRejection) -> Self {
+        ServerError::Custom {
+            status: json_rejection.status(),
+            message: json_rejection.body_text(),
+        }
+    }
+}
 impl From<RenderError> for ServerError {
     fn from(render_error: RenderError) -> Self {
         ServerError::InternalError(render_error.to_string())
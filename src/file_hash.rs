@@ -0,0 +1,151 @@
+use std::fmt;
+use std::fs::File as StdFile;
+use std::io::{self, Write};
+use std::path::Path as StdPath;
+use std::str::FromStr;
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+/// The expected hash of a whole file, written as `<algorithm>:<hex digest>`,
+/// e.g. `sha256:88d4266f…` or `blake3:…`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileHash {
+    Sha256([u8; 32]),
+    Blake3([u8; 32]),
+}
+impl FileHash {
+    /// Hash the file at `path` with the same algorithm and compare.
+    pub fn matches_file(&self, path: &StdPath) -> io::Result<bool> {
+        let mut file = StdFile::open(path)?;
+        let digest: [u8; 32] = match self {
+            Self::Sha256(_) => {
+                let mut hasher = Sha256::new();
+                io::copy(&mut file, &mut hasher)?;
+                hasher.finalize().into()
+            }
+            Self::Blake3(_) => {
+                let mut hasher = blake3::Hasher::new();
+                io::copy(&mut file, &mut hasher)?;
+                hasher.flush()?;
+                hasher.finalize().into()
+            }
+        };
+        Ok(&digest == self.digest())
+    }
+
+    fn algorithm(&self) -> &'static str {
+        match self {
+            Self::Sha256(_) => "sha256",
+            Self::Blake3(_) => "blake3",
+        }
+    }
+
+    fn digest(&self) -> &[u8; 32] {
+        match self {
+            Self::Sha256(digest) | Self::Blake3(digest) => digest,
+        }
+    }
+}
+impl fmt::Display for FileHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.algorithm())?;
+        for byte in self.digest() {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+impl FromStr for FileHash {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (algorithm, hex) = s
+            .split_once(':')
+            .context("expected `<algorithm>:<hex digest>`")?;
+        let digest = parse_hex_digest(hex).context("expected a 64 digit hex digest")?;
+        match algorithm {
+            "sha256" => Ok(Self::Sha256(digest)),
+            "blake3" => Ok(Self::Blake3(digest)),
+            _ => bail!("expected `sha256` or `blake3`, got `{algorithm}`"),
+        }
+    }
+}
+impl Serialize for FileHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl<'de> Deserialize<'de> for FileHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Parse a 32 byte digest written as 64 hex digits, in either case.
+pub fn parse_hex_digest(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut digest = [0u8; 32];
+    for (byte, pair) in digest.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const ABCD_SHA256: &str =
+        "sha256:88d4266fd4e6338d13b845fcf289579d209c897823b9217da3e161936f031589";
+
+    #[test]
+    fn round_trips_through_strings() {
+        let hash: FileHash = ABCD_SHA256.parse().unwrap();
+        assert_eq!(hash.to_string(), ABCD_SHA256);
+        assert_eq!(
+            serde_json::to_string(&hash).unwrap(),
+            format!("\"{ABCD_SHA256}\"")
+        );
+        assert_eq!(
+            serde_json::from_str::<FileHash>(&format!("\"{ABCD_SHA256}\"")).unwrap(),
+            hash
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_hashes() {
+        for hash in [
+            "88d4266fd4e6338d13b845fcf289579d209c897823b9217da3e161936f031589",
+            "md5:88d4266fd4e6338d13b845fcf289579d209c897823b9217da3e161936f031589",
+            "sha256:88d4",
+            "sha256:zzd4266fd4e6338d13b845fcf289579d209c897823b9217da3e161936f031589",
+        ] {
+            assert!(hash.parse::<FileHash>().is_err(), "{hash}");
+        }
+    }
+
+    #[test]
+    fn matches_file_content() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("data");
+        std::fs::write(&path, b"abcd").unwrap();
+
+        let sha256: FileHash = ABCD_SHA256.parse().unwrap();
+        assert!(sha256.matches_file(&path).unwrap());
+
+        let blake3 = FileHash::Blake3(blake3::hash(b"abcd").into());
+        assert!(blake3.matches_file(&path).unwrap());
+
+        assert!(!FileHash::Blake3([0; 32]).matches_file(&path).unwrap());
+
+        std::fs::write(&path, b"abce").unwrap();
+        assert!(!sha256.matches_file(&path).unwrap());
+    }
+}
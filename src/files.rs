@@ -1,23 +1,40 @@
-use std::io::{Error as IoError, SeekFrom};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, SeekFrom};
+use std::path::{Path as StdPath, PathBuf};
 
 use axum::body::Body;
-use axum::extract::{Multipart, OriginalUri, Path};
-use axum::http::header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use axum::body::Bytes;
+use axum::extract::{Form, FromRequest, Multipart, OriginalUri, Path, Query, Request};
+use axum::http::header::{
+    ACCEPT, ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, HOST,
+    RANGE,
+};
 use axum::http::{HeaderMap, HeaderValue, StatusCode};
-use axum::response::{Html, IntoResponse};
-use futures::{TryStreamExt, pin_mut};
+use axum::response::{Html, IntoResponse, Json, Response};
+use futures::{Stream, TryStreamExt, pin_mut};
+use rand::{RngExt, rng};
 use sailfish::TemplateOnce;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
 use tokio::fs;
-use tokio::io::{AsyncReadExt, AsyncSeekExt, BufWriter};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter};
 use tokio::task::spawn_blocking;
 use tokio_util::io::{ReaderStream, StreamReader};
 
+use crate::archive::{self, ArchiveFormat};
 use crate::auth::Claims;
 use crate::config::CONFIG;
+use crate::conflict::{self, ConflictPolicy};
 use crate::errors::ServerError;
-use crate::safe_path::safe_join;
+use crate::fs_utils::rename_no_replace;
+use crate::role::Capability;
+use crate::safe_path::{STATE_DIR_NAME, safe_join};
+use crate::share;
 use crate::templates;
+use crate::upload::{self, is_upload_artifact, is_upload_artifact_sync, staging_root};
+use crate::uploads;
 
 pub const ROUTE_PATH: &str = "/files/{*file_path}";
 pub const ROUTE_PATH_ROOT: &str = "/files";
@@ -110,31 +127,187 @@ fn parse_range(header: Option<&HeaderValue>, file_size: u64) -> RangeSpec {
     }
 }
 
+/// The representation of a directory listing.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ListingFormat {
+    Html,
+    Json,
+}
+
+#[derive(Deserialize, Default)]
+pub struct FilesGetQuery {
+    /// Overrides the listing format negotiated via the `Accept` header.
+    format: Option<ListingFormat>,
+
+    /// Download a directory as an archive of this format instead of listing it.
+    archive: Option<ArchiveFormat>,
+}
+
+/// Pick the listing format: an explicit `?format=` wins, otherwise JSON is
+/// only returned when the client explicitly accepts it, so browsers (which
+/// send `Accept: text/html, ..., */*`) keep getting the HTML page.
+pub fn listing_format(headers: &HeaderMap, requested: Option<ListingFormat>) -> ListingFormat {
+    if let Some(format) = requested {
+        return format;
+    }
+
+    if accepts_json(headers) {
+        ListingFormat::Json
+    } else {
+        ListingFormat::Html
+    }
+}
+
+/// Whether the `Accept` header lists `application/json`, i.e. the request
+/// comes from a script rather than a browser navigation. A media range with
+/// `q=0` means the client does not accept it.
+fn accepts_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_range| {
+            let mut parts = media_range.split(';');
+            parts
+                .next()
+                .is_some_and(|media_type| media_type.trim() == "application/json")
+                && parts.all(|parameter| {
+                    !parameter.split_once('=').is_some_and(|(name, value)| {
+                        name.trim().eq_ignore_ascii_case("q")
+                            && value.trim().parse::<f32>().is_ok_and(|q| q <= 0.0)
+                    })
+                })
+        })
+}
+
+/// A directory listing as returned to programmatic clients.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DirectoryListing<'a> {
+    path: &'a str,
+    entries: Vec<DirectoryListingEntry>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+enum DirectoryListingEntryType {
+    File,
+    Directory,
+    /// A resumable upload that hasn't completed yet.
+    Uploading,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DirectoryListingEntry {
+    name: String,
+    #[serde(rename = "type")]
+    entry_type: DirectoryListingEntryType,
+    /// File size in bytes. For directories, it's always 0.
+    size: u64,
+    #[serde(with = "time::serde::rfc3339")]
+    modified: OffsetDateTime,
+    /// The MIME type guessed from the file extension, `None` for directories.
+    mime: Option<String>,
+    /// Completed percentage of an upload in progress.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    progress: Option<u8>,
+}
+impl From<templates::FilesEntry> for DirectoryListingEntry {
+    fn from(entry: templates::FilesEntry) -> Self {
+        Self {
+            mime: if entry.is_dir {
+                None
+            } else {
+                Some(
+                    mime_guess::from_path(&entry.name)
+                        .first_or_octet_stream()
+                        .to_string(),
+                )
+            },
+            entry_type: if entry.is_dir {
+                DirectoryListingEntryType::Directory
+            } else if entry.upload_progress.is_some() {
+                DirectoryListingEntryType::Uploading
+            } else {
+                DirectoryListingEntryType::File
+            },
+            name: entry.name,
+            size: entry.size,
+            modified: entry.modified,
+            progress: entry.upload_progress,
+        }
+    }
+}
+
 pub async fn root_get(
     claims: Claims,
+    query: Query<FilesGetQuery>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, ServerError> {
-    get(claims, Path("".to_string()), headers).await
+    get(claims, Path("".to_string()), query, headers).await
 }
 
 pub async fn get(
     claims: Claims,
     Path(path): Path<String>,
+    Query(query): Query<FilesGetQuery>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, ServerError> {
-    let full_path = safe_join(&CONFIG.store_path, &path)?;
+    let full_path = safe_join(claims.root(), &path)?;
 
-    let metadata = fs::metadata(&full_path).await?;
+    // Half-written uploads must never be mistaken for the real file.
+    if is_upload_artifact(&full_path).await {
+        return Err(IoError::from(IoErrorKind::NotFound).into());
+    }
 
-    if metadata.is_dir() {
-        let entries = spawn_blocking::<_, Result<_, IoError>>(move || {
-            let mut entries = std::fs::read_dir(&full_path)?
-                .map(|entry| {
+    // Users who can't read a directory still get its file explorer to upload
+    // to, or to browse through towards what their access rules allow, listing
+    // only the entries they may see.
+    let can_read = claims.can_at(Capability::Read, &full_path);
+    let can_browse = claims.can_browse(&full_path);
+    let metadata = fs::metadata(&full_path).await;
+    let is_dir = metadata.as_ref().is_ok_and(|metadata| metadata.is_dir());
+    if is_dir && query.archive.is_some() {
+        claims.authorize_tree(Capability::Read, &full_path)?;
+    } else if !can_read
+        && !(is_dir
+            && (can_browse
+                || listing_format(&headers, query.format) == ListingFormat::Html
+                    && claims.can_at(Capability::Write, &full_path)))
+    {
+        return Err(ServerError::Forbidden);
+    }
+    let metadata = metadata?;
+
+    if metadata.is_dir()
+        && let Some(format) = query.archive
+    {
+        let root_name = match path.trim_end_matches('/').rsplit('/').next() {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => "files".to_string(),
+        };
+        Ok(archive_response(full_path, root_name, format))
+    } else if metadata.is_dir() {
+        let entries = if !can_read && !can_browse {
+            Vec::new()
+        } else {
+            let dir_path = full_path.clone();
+            let mut entries = spawn_blocking::<_, Result<_, IoError>>(move || {
+                let full_path = dir_path;
+                let mut entries = Vec::new();
+                for entry in std::fs::read_dir(&full_path)? {
                     let entry = entry?;
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    if name == STATE_DIR_NAME || is_upload_artifact_sync(&entry.path()) {
+                        continue;
+                    }
                     let entry_metadata = entry.metadata()?;
 
-                    Ok(templates::FilesEntry {
-                        name: entry.file_name().to_string_lossy().to_string(),
+                    entries.push(templates::FilesEntry {
+                        name,
                         modified: OffsetDateTime::from(entry_metadata.modified()?),
                         is_dir: entry_metadata.is_dir(),
                         size: if entry_metadata.is_dir() {
@@ -142,15 +315,53 @@ pub async fn get(
                         } else {
                             entry_metadata.len()
                         },
-                    })
-                })
-                .collect::<Result<Vec<_>, IoError>>()?;
+                        upload_progress: None,
+                    });
+                }
+
+                // Resumable uploads in progress targeting this directory.
+                let dir = full_path
+                    .strip_prefix(&CONFIG.store_path)
+                    .unwrap_or(&full_path);
+                for (upload_dir, meta) in
+                    upload::list_uploads_sync(&staging_root(&CONFIG.store_path))
+                {
+                    let target = StdPath::new(meta.target());
+                    if target.parent() != Some(dir) {
+                        continue;
+                    }
+                    let Some(name) = target.file_name() else {
+                        continue;
+                    };
+                    // The upload may have finished or been removed since.
+                    let Some(modified) = upload::last_activity(&upload_dir) else {
+                        continue;
+                    };
+                    entries.push(templates::FilesEntry {
+                        name: name.to_string_lossy().to_string(),
+                        modified: OffsetDateTime::from(modified),
+                        is_dir: false,
+                        size: meta.file_size(),
+                        upload_progress: Some(meta.progress()),
+                    });
+                }
 
-            entries.sort_unstable();
+                entries.sort_unstable();
 
-            Ok(entries)
-        })
-        .await??;
+                Ok(entries)
+            })
+            .await??;
+            entries.retain(|entry| is_visible(&claims, &full_path.join(&entry.name), entry.is_dir));
+            entries
+        };
+
+        if listing_format(&headers, query.format) == ListingFormat::Json {
+            return Ok(Json(DirectoryListing {
+                path: path.trim_matches('/'),
+                entries: entries.into_iter().map(Into::into).collect(),
+            })
+            .into_response());
+        }
 
         let upload_uri = if path.is_empty() {
             ROUTE_PATH_ROOT_EMPTY.to_string()
@@ -161,130 +372,760 @@ pub async fn get(
         };
 
         let files_template = templates::Files {
+            upload_conflict: allowed_conflict_policy(&claims, CONFIG.upload_conflict, &full_path),
             claims,
             path_prefix: ROUTE_PATH_ROOT,
             path: &path,
+            full_path,
             entries,
             upload_uri,
+            uploads_uri: uploads::ROUTE_PATH,
         };
 
         Ok(Html(files_template.render_once()?).into_response())
     } else {
-        let file_size = metadata.len();
-        let mime = mime_guess::from_path(&full_path)
-            .first_or_octet_stream()
-            .to_string();
-
-        match parse_range(headers.get(RANGE), file_size) {
-            RangeSpec::Ignore => {
-                let file = fs::File::open(&full_path).await?;
-                Ok((
-                    [
-                        (CONTENT_TYPE, mime),
-                        (CONTENT_LENGTH, file_size.to_string()),
-                        (ACCEPT_RANGES, "bytes".to_string()),
-                    ],
-                    Body::from_stream(ReaderStream::with_capacity(file, READ_BUFFER_SIZE)),
-                )
-                    .into_response())
-            }
-            RangeSpec::Unsatisfiable => Ok((
-                StatusCode::RANGE_NOT_SATISFIABLE,
+        serve_file(&full_path, metadata.len(), &headers).await
+    }
+}
+
+/// Stream the directory at `full_path` as an archive named after `root_name`.
+pub fn archive_response(full_path: PathBuf, root_name: String, format: ArchiveFormat) -> Response {
+    let file_name = format!("{root_name}.{}", format.extension());
+
+    (
+        [
+            (CONTENT_TYPE, format.content_type().to_string()),
+            (
+                CONTENT_DISPOSITION,
+                archive::content_disposition(&file_name),
+            ),
+        ],
+        archive::stream(full_path, root_name, format),
+    )
+        .into_response()
+}
+
+/// Stream the file at `full_path` of `file_size` bytes, or the part of it
+/// asked for by a `Range` header.
+pub async fn serve_file(
+    full_path: &StdPath,
+    file_size: u64,
+    headers: &HeaderMap,
+) -> Result<Response, ServerError> {
+    let mime = mime_guess::from_path(full_path)
+        .first_or_octet_stream()
+        .to_string();
+
+    match parse_range(headers.get(RANGE), file_size) {
+        RangeSpec::Ignore => {
+            let file = fs::File::open(full_path).await?;
+            Ok((
                 [
-                    (CONTENT_RANGE, format!("bytes */{file_size}")),
+                    (CONTENT_TYPE, mime),
+                    (CONTENT_LENGTH, file_size.to_string()),
                     (ACCEPT_RANGES, "bytes".to_string()),
                 ],
-                Body::empty(),
+                Body::from_stream(ReaderStream::with_capacity(file, READ_BUFFER_SIZE)),
             )
-                .into_response()),
-            RangeSpec::Satisfiable {
-                start,
-                end_inclusive,
-            } => {
-                let mut file = fs::File::open(&full_path).await?;
-                file.seek(SeekFrom::Start(start)).await?;
-                let length = end_inclusive - start + 1;
-                Ok((
-                    StatusCode::PARTIAL_CONTENT,
-                    [
-                        (CONTENT_TYPE, mime),
-                        (CONTENT_LENGTH, length.to_string()),
-                        (
-                            CONTENT_RANGE,
-                            format!("bytes {start}-{end_inclusive}/{file_size}"),
-                        ),
-                        (ACCEPT_RANGES, "bytes".to_string()),
-                    ],
-                    Body::from_stream(ReaderStream::with_capacity(
-                        file.take(length),
-                        READ_BUFFER_SIZE,
-                    )),
-                )
-                    .into_response())
-            }
+                .into_response())
+        }
+        RangeSpec::Unsatisfiable => Ok((
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [
+                (CONTENT_RANGE, format!("bytes */{file_size}")),
+                (ACCEPT_RANGES, "bytes".to_string()),
+            ],
+            Body::empty(),
+        )
+            .into_response()),
+        RangeSpec::Satisfiable {
+            start,
+            end_inclusive,
+        } => {
+            let mut file = fs::File::open(full_path).await?;
+            file.seek(SeekFrom::Start(start)).await?;
+            let length = end_inclusive - start + 1;
+            Ok((
+                StatusCode::PARTIAL_CONTENT,
+                [
+                    (CONTENT_TYPE, mime),
+                    (CONTENT_LENGTH, length.to_string()),
+                    (
+                        CONTENT_RANGE,
+                        format!("bytes {start}-{end_inclusive}/{file_size}"),
+                    ),
+                    (ACCEPT_RANGES, "bytes".to_string()),
+                ],
+                Body::from_stream(ReaderStream::with_capacity(
+                    file.take(length),
+                    READ_BUFFER_SIZE,
+                )),
+            )
+                .into_response())
         }
     }
 }
 
+/// The operation a `POST /files/{path}` request performs.
+/// HTML forms can only send `GET` and `POST`, so every mutating action the
+/// file explorer offers without JavaScript is multiplexed onto `POST` through
+/// the `action` query parameter. A missing `action` means a multipart upload.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilesAction {
+    #[default]
+    Upload,
+    Delete,
+    Move,
+    Mkdir,
+    Share,
+}
+
+#[derive(Deserialize, Default)]
+pub struct FilesPostQuery {
+    #[serde(default)]
+    action: FilesAction,
+
+    /// Overrides the configured conflict policy for an upload.
+    conflict: Option<ConflictPolicy>,
+}
+
 pub async fn root_post(
     claims: Claims,
+    query: Query<FilesPostQuery>,
     uri: OriginalUri,
-    multipart: Multipart,
-) -> Result<impl IntoResponse, ServerError> {
-    post(claims, Path("".to_string()), uri, multipart).await
+    request: Request,
+) -> Result<Response, ServerError> {
+    post(claims, Path("".to_string()), query, uri, request).await
 }
 
 pub async fn post(
-    _: Claims,
+    claims: Claims,
     Path(path): Path<String>,
+    Query(query): Query<FilesPostQuery>,
     OriginalUri(uri): OriginalUri,
+    request: Request,
+) -> Result<Response, ServerError> {
+    match query.action {
+        FilesAction::Upload => {
+            // Every file is authorized on its own, see `upload`.
+            claims.require(Capability::Write)?;
+            let wants_json = accepts_json(request.headers());
+            let multipart = Multipart::from_request(request, &()).await?;
+            let conflict = query.conflict.unwrap_or(CONFIG.upload_conflict);
+            let dir_path = safe_join(claims.root(), &path)?;
+            upload(
+                &claims,
+                &dir_path,
+                uri.path(),
+                wants_json,
+                conflict,
+                multipart,
+            )
+            .await
+        }
+
+        FilesAction::Delete => {
+            let action = ActionRequest::<DeleteForm>::from_request(request).await?;
+            let full_path = safe_join_entry(claims.root(), &path)?;
+            authorize_entry(&claims, Capability::Delete, &full_path).await?;
+            remove_entry(&full_path, action.body.recursive).await?;
+
+            if action.is_json {
+                return Ok(StatusCode::NO_CONTENT.into_response());
+            }
+
+            Ok(Html(
+                templates::Redirect {
+                    title: "Delete Successful",
+                    url: &parent_uri(&path),
+                    success: true,
+                    message: "Now you'll be redirected to the file explorer.",
+                    ..Default::default()
+                }
+                .render_once()?,
+            )
+            .into_response())
+        }
+
+        FilesAction::Move => {
+            let action = ActionRequest::<MoveForm>::from_request(request).await?;
+            let destination = action.body.destination.trim_start_matches('/');
+            let source_path = safe_join_entry(claims.root(), &path)?;
+            let destination_path = safe_join_entry(claims.root(), destination)?;
+            // Moving takes the entry away from the source and creates it at
+            // the destination, replacing what is there with `overwrite`.
+            let is_dir = authorize_entry(&claims, Capability::Delete, &source_path).await?;
+            if is_dir {
+                claims.authorize_tree(Capability::Write, &destination_path)?;
+            } else {
+                claims.authorize(Capability::Write, &destination_path)?;
+            }
+            if action.body.overwrite {
+                authorize_entry(&claims, Capability::Delete, &destination_path).await?;
+            }
+            move_entry(source_path, destination_path, action.body.overwrite).await?;
+
+            if action.is_json {
+                return Ok(Json(json!({ "path": destination })).into_response());
+            }
+
+            Ok(Html(
+                templates::Redirect {
+                    title: "Move Successful",
+                    url: &parent_uri(destination),
+                    success: true,
+                    message: "Now you'll be redirected to the file explorer.",
+                    ..Default::default()
+                }
+                .render_once()?,
+            )
+            .into_response())
+        }
+
+        FilesAction::Mkdir => {
+            let action = ActionRequest::<MkdirForm>::from_request(request).await?;
+            let name = action.body.name.trim_matches('/');
+            if name.is_empty() {
+                return Err(ServerError::InvalidPath);
+            }
+            let dir_path = safe_join(&safe_join(claims.root(), &path)?, name)?;
+            claims.authorize(Capability::Write, &dir_path)?;
+            create_dir(&dir_path).await?;
+
+            if action.is_json {
+                let created_path = match path.trim_matches('/') {
+                    "" => name.to_string(),
+                    parent => format!("{parent}/{name}"),
+                };
+                return Ok(
+                    (StatusCode::CREATED, Json(json!({ "path": created_path }))).into_response()
+                );
+            }
+
+            Ok(Html(
+                templates::Redirect {
+                    title: "Folder Created",
+                    url: uri.path(),
+                    success: true,
+                    message: "Now you'll be redirected to the file explorer.",
+                    ..Default::default()
+                }
+                .render_once()?,
+            )
+            .into_response())
+        }
+
+        FilesAction::Share => {
+            let origin = public_origin(request.headers());
+            let action = ActionRequest::<share::ShareForm>::from_request(request).await?;
+            let full_path = safe_join(claims.root(), &path)?;
+            let created = share::create(&claims, &full_path, action.body).await?;
+
+            if action.is_json {
+                return Ok((StatusCode::CREATED, Json(created)).into_response());
+            }
+
+            let link = format!("{origin}{}", created.url);
+            let message = format!(
+                "Anyone with the link below can download \"{}\" until {}.",
+                full_path
+                    .file_name()
+                    .map_or("the store".into(), |name| name.to_string_lossy()),
+                created.expires_at.format(&Rfc3339).unwrap_or_default()
+            );
+            Ok(Html(
+                templates::Redirect {
+                    title: "Link Created",
+                    // Give people time to copy the link.
+                    time: Some(120),
+                    url: &parent_uri(&path),
+                    icon: Some("🔗"),
+                    message: &message,
+                    results: vec![templates::RedirectResult {
+                        name: link,
+                        error: None,
+                    }],
+                    ..Default::default()
+                }
+                .render_once()?,
+            )
+            .into_response())
+        }
+    }
+}
+
+/// The body of a non-upload action. API clients send JSON, while the file
+/// explorer submits plain urlencoded forms; both carry the same fields.
+struct ActionRequest<T> {
+    body: T,
+    is_json: bool,
+}
+impl<T: DeserializeOwned> ActionRequest<T> {
+    async fn from_request(request: Request) -> Result<Self, ServerError> {
+        let is_json = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("application/json"));
+
+        let body = if is_json {
+            Json::<T>::from_request(request, &()).await?.0
+        } else {
+            Form::<T>::from_request(request, &()).await?.0
+        };
+
+        Ok(Self { body, is_json })
+    }
+}
+
+/// Outcome of storing one file of a multipart upload.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadedFile {
+    /// The file name as submitted, including any relative directories.
+    name: String,
+
+    /// Where the file was stored relative to the upload directory. Differs
+    /// from `name` when the `auto-rename` conflict policy picked a new name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+
+    /// Why the file couldn't be stored, `None` on success.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Store every `file` field of the form. Folder uploads submit their files
+/// with names relative to the picked folder, e.g. `photos/2024/a.jpg`; the
+/// missing subdirectories are created on the way. A file that fails doesn't
+/// abort the rest of the batch, the failure is reported next to its name.
+///
+/// A `conflict` field overrides `conflict` for the files that follow it, so
+/// forms have to put it before their file inputs.
+async fn upload(
+    claims: &Claims,
+    dir_path: &StdPath,
+    redirect_uri: &str,
+    wants_json: bool,
+    mut conflict: ConflictPolicy,
     mut multipart: Multipart,
-) -> Result<impl IntoResponse, ServerError> {
-    let dir_path = safe_join(&CONFIG.store_path, &path)?;
+) -> Result<Response, ServerError> {
+    let mut uploaded_files = Vec::new();
 
-    let field = multipart
-        .next_field()
-        .await?
-        .ok_or(ServerError::InvalidUploadForm)?;
+    while let Some(field) = multipart.next_field().await? {
+        if field.name() == Some("conflict") {
+            conflict = field
+                .text()
+                .await?
+                .parse()
+                .map_err(|_| ServerError::InvalidUploadForm)?;
+            continue;
+        }
+        if field.name() != Some("file") {
+            continue;
+        }
+        let Some(file_name) = field.file_name().map(str::to_string) else {
+            return Err(ServerError::InvalidUploadForm);
+        };
+        // Browsers submit an empty part without a name when no file was picked.
+        if file_name.is_empty() {
+            continue;
+        }
+
+        let stored = async {
+            let file_path = safe_join(dir_path, &file_name)?;
+            claims.authorize(Capability::Write, &file_path)?;
+            let conflict = allowed_conflict_policy(claims, conflict, &file_path);
+            let temp_dir = upload::form_upload_root(&CONFIG.store_path);
+            store_upload(dir_path, &file_name, conflict, &temp_dir, field).await
+        }
+        .await;
+        let (path, error) = match stored {
+            Ok(stored_name) => (Some(stored_name), None),
+            Err(err) => (None, Some(describe_upload_error(&err))),
+        };
+        uploaded_files.push(UploadedFile {
+            name: file_name,
+            path,
+            error,
+        });
+    }
 
-    if field.name() != Some("file") {
+    if uploaded_files.is_empty() {
         return Err(ServerError::InvalidUploadForm);
     }
-    let file_name = if let Some(file_name) = field.file_name() {
-        file_name.to_string()
+
+    if wants_json {
+        return Ok(Json(json!({ "files": uploaded_files })).into_response());
+    }
+
+    let failed = uploaded_files
+        .iter()
+        .filter(|file| file.error.is_some())
+        .count();
+    let total = uploaded_files.len();
+    let (title, message) = if failed == 0 {
+        (
+            "Upload Successful",
+            "Now you'll be redirected to the file explorer.".to_string(),
+        )
+    } else if failed == total {
+        ("Upload Failed", "No file could be uploaded.".to_string())
     } else {
-        return Err(ServerError::InvalidUploadForm);
+        (
+            "Upload Partially Successful",
+            format!("{} of {total} files were uploaded.", total - failed),
+        )
     };
+    // A single file stored under its own name needs no breakdown, that's what
+    // the title says.
+    let results = if total == 1 && uploaded_files[0].path.as_ref() == Some(&uploaded_files[0].name)
+    {
+        Vec::new()
+    } else {
+        uploaded_files
+            .into_iter()
+            .map(|file| templates::RedirectResult {
+                name: match file.path {
+                    Some(path) if path != file.name => format!("{} → {path}", file.name),
+                    _ => file.name,
+                },
+                error: file.error,
+            })
+            .collect()
+    };
+
+    Ok(Html(
+        templates::Redirect {
+            title,
+            // Give people time to read which files failed before leaving.
+            time: (failed > 0).then_some(10),
+            url: redirect_uri,
+            success: failed == 0,
+            message: &message,
+            results,
+            ..Default::default()
+        }
+        .render_once()?,
+    )
+    .into_response())
+}
 
-    let file_path = safe_join(&dir_path, &format!("{file_name}.form-upload"))?;
+/// Store one uploaded file, a multipart field or a raw request body, returning
+/// the name it was stored under relative to `dir_path`. The file is streamed
+/// to a temporary file in `temp_dir` first, so a failed upload never leaves a
+/// truncated file behind. `temp_dir` has to be on the same file system.
+async fn store_upload<S, E>(
+    dir_path: &StdPath,
+    file_name: &str,
+    conflict: ConflictPolicy,
+    temp_dir: &StdPath,
+    body: S,
+) -> Result<String, ServerError>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let final_file_path = safe_join(dir_path, file_name)?;
+    let file_path = temp_dir.join(format!("{:032x}", rng().random::<u128>()));
+    if let Some(parent) = final_file_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::create_dir_all(temp_dir).await?;
 
-    let body_with_io_error = field.map_err(IoError::other);
+    let body_with_io_error = body.map_err(IoError::other);
     let body_reader = StreamReader::new(body_with_io_error);
     pin_mut!(body_reader);
     let mut file = BufWriter::new(fs::File::create(&file_path).await?);
 
-    tokio::io::copy(&mut body_reader, &mut file).await?;
+    let copied = async {
+        tokio::io::copy(&mut body_reader, &mut file).await?;
+        file.flush().await
+    }
+    .await;
+    if let Err(err) = copied {
+        let _ = fs::remove_file(&file_path).await;
+        return Err(err.into());
+    }
 
-    let final_file_path = safe_join(&dir_path, &file_name)?;
+    let stored_path = match conflict::place(file_path.clone(), final_file_path, conflict).await {
+        Ok(stored_path) => stored_path,
+        Err(err) => {
+            let _ = fs::remove_file(&file_path).await;
+            return Err(err);
+        }
+    };
 
-    fs::rename(file_path, final_file_path).await?;
+    Ok(stored_path
+        .strip_prefix(dir_path)
+        .unwrap_or(&stored_path)
+        .to_string_lossy()
+        .into_owned())
+}
 
-    Ok(Html(
-        templates::Redirect {
-            title: "Upload Successful",
-            url: uri.path(),
-            success: true,
-            message: "Now you'll be redirected to the file explorer.",
-            ..Default::default()
+fn describe_upload_error(err: &ServerError) -> String {
+    match err {
+        ServerError::InvalidPath => "Invalid file path.".to_string(),
+        ServerError::FileConflict => "A file with the same name already exists.".to_string(),
+        ServerError::Forbidden => "You are not allowed to upload here.".to_string(),
+        ServerError::IoError(err) => err.to_string(),
+        ServerError::Custom { message, .. } => message.clone(),
+        _ => "The file could not be stored.".to_string(),
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct FilesPutQuery {
+    /// Overrides the configured conflict policy.
+    conflict: Option<ConflictPolicy>,
+}
+
+/// Upload a file as the raw request body, e.g. with `curl -T`. Missing parent
+/// directories are created, and an existing file is handled according to the
+/// conflict policy. Responds with where the file was stored, relative to the
+/// user's root.
+pub async fn put(
+    claims: Claims,
+    Path(path): Path<String>,
+    Query(query): Query<FilesPutQuery>,
+    body: Body,
+) -> Result<impl IntoResponse, ServerError> {
+    // The target has to name a file, neither a directory nor the store root.
+    if path.ends_with('/') {
+        return Err(ServerError::InvalidPath);
+    }
+    let file_path = safe_join_entry(claims.root(), &path)?;
+    claims.authorize(Capability::Write, &file_path)?;
+
+    let conflict = allowed_conflict_policy(
+        &claims,
+        query.conflict.unwrap_or(CONFIG.upload_conflict),
+        &file_path,
+    );
+    let stored_path = store_upload(
+        claims.root(),
+        &path,
+        conflict,
+        &upload::form_upload_root(&CONFIG.store_path),
+        body.into_data_stream(),
+    )
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(json!({ "path": stored_path }))))
+}
+
+#[derive(Deserialize, Default)]
+pub struct DeleteForm {
+    /// Remove a non-empty directory together with everything inside it.
+    /// Without this flag only files and empty directories can be deleted.
+    #[serde(default)]
+    recursive: bool,
+}
+
+#[derive(Deserialize)]
+pub struct MoveForm {
+    /// The new path of the entry, relative to the user's root.
+    destination: String,
+
+    /// Replace the destination if it already exists.
+    /// Without this flag the move fails instead.
+    #[serde(default)]
+    overwrite: bool,
+}
+
+#[derive(Deserialize)]
+pub struct MkdirForm {
+    /// The name of the new directory, relative to the directory it is created in.
+    /// It may contain slashes, in which case missing parents are created too.
+    name: String,
+}
+
+/// Delete a file or a directory.
+pub async fn delete(
+    claims: Claims,
+    Path(path): Path<String>,
+    Query(query): Query<DeleteForm>,
+) -> Result<impl IntoResponse, ServerError> {
+    let full_path = safe_join_entry(claims.root(), &path)?;
+    authorize_entry(&claims, Capability::Delete, &full_path).await?;
+    remove_entry(&full_path, query.recursive).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The conflict policy an upload of the user to `file_path` actually gets.
+/// Overwriting a file deletes it, so users who can't delete it get `reject`
+/// instead.
+pub fn allowed_conflict_policy(
+    claims: &Claims,
+    conflict: ConflictPolicy,
+    file_path: &StdPath,
+) -> ConflictPolicy {
+    match conflict {
+        ConflictPolicy::Overwrite if !claims.can_at(Capability::Delete, file_path) => {
+            ConflictPolicy::Reject
         }
-        .render_once()?,
-    ))
+        _ => conflict,
+    }
+}
+
+/// Authorize `capability` on the entry at `full_path`, and everything inside
+/// it if it is a directory. Returns whether it is one.
+async fn authorize_entry(
+    claims: &Claims,
+    capability: Capability,
+    full_path: &StdPath,
+) -> Result<bool, ServerError> {
+    let is_dir = fs::symlink_metadata(full_path)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir());
+    if is_dir {
+        claims.authorize_tree(capability, full_path)?;
+    } else {
+        claims.authorize(capability, full_path)?;
+    }
+    Ok(is_dir)
+}
+
+/// Whether a directory listing shows the entry at `full_path` to the user:
+/// files they can read, and directories they can do anything in or browse
+/// through.
+fn is_visible(claims: &Claims, full_path: &StdPath, is_dir: bool) -> bool {
+    claims.can_at(Capability::Read, full_path)
+        || is_dir && (claims.can_at(Capability::Write, full_path) || claims.can_browse(full_path))
+}
+
+/// Like [`safe_join`] onto the user's `root`, but refuses the root itself,
+/// which must never be deleted or moved.
+fn safe_join_entry(root: &StdPath, path: &str) -> Result<std::path::PathBuf, ServerError> {
+    if path.trim_matches('/').is_empty() {
+        return Err(ServerError::CannotModifyStoreRoot);
+    }
+    safe_join(root, path)
+}
+
+/// Remove `full_path`. Directories are only removed when they are empty,
+/// unless `recursive` is set. Symlinks are removed themselves, never followed.
+async fn remove_entry(full_path: &StdPath, recursive: bool) -> Result<(), ServerError> {
+    let metadata = fs::symlink_metadata(full_path).await?;
+
+    if !metadata.is_dir() {
+        fs::remove_file(full_path).await?;
+    } else if recursive {
+        fs::remove_dir_all(full_path).await?;
+    } else {
+        fs::remove_dir(full_path).await.map_err(|err| {
+            if err.kind() == IoErrorKind::DirectoryNotEmpty {
+                ServerError::DirectoryIsNotEmpty
+            } else {
+                err.into()
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Create the directory `dir_path` along with any missing parents, like
+/// `mkdir -p`, except that the directory itself must not exist yet.
+async fn create_dir(dir_path: &StdPath) -> Result<(), ServerError> {
+    if let Some(parent) = dir_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    fs::create_dir(dir_path).await.map_err(|err| {
+        if err.kind() == IoErrorKind::AlreadyExists {
+            ServerError::DirectoryAlreadyExists
+        } else {
+            err.into()
+        }
+    })
+}
+
+/// Move (or rename) `source` to `destination`. An existing destination is only
+/// replaced when `overwrite` is set, and only by an entry of the same type, in
+/// which case a destination directory is removed together with its content
+/// first.
+async fn move_entry(
+    source: PathBuf,
+    destination: PathBuf,
+    overwrite: bool,
+) -> Result<(), ServerError> {
+    let source_metadata = fs::symlink_metadata(&source).await?;
+
+    if source == destination {
+        return Ok(());
+    }
+    if destination.starts_with(&source) {
+        return Err(ServerError::CannotMoveIntoItself);
+    }
+    // Replacing a directory the source is inside of would delete the source.
+    if source.starts_with(&destination) {
+        return Err(ServerError::CannotReplaceParent);
+    }
+
+    if !overwrite {
+        return rename_no_replace(source, destination).await.map_err(|err| {
+            if err.kind() == IoErrorKind::AlreadyExists {
+                ServerError::DestinationAlreadyExists
+            } else {
+                err.into()
+            }
+        });
+    }
+
+    match fs::symlink_metadata(&destination).await {
+        Ok(metadata) if metadata.is_dir() != source_metadata.is_dir() => {
+            return Err(ServerError::DestinationTypeMismatch);
+        }
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&destination).await?,
+        Ok(_) => {}
+        Err(err) if err.kind() == IoErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    fs::rename(source, destination).await?;
+
+    Ok(())
+}
+
+/// The scheme and host the client reached the server at, to turn paths into
+/// links that can be sent to others.
+fn public_origin(headers: &HeaderMap) -> String {
+    let scheme = if CONFIG.tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    match headers.get(HOST).and_then(|host| host.to_str().ok()) {
+        Some(host) => format!("{scheme}://{host}"),
+        None => String::new(),
+    }
+}
+
+/// The file explorer URI of the directory containing `path`.
+fn parent_uri(path: &str) -> String {
+    match path.trim_end_matches('/').rsplit_once('/') {
+        Some((parent, _)) => format!("{ROUTE_PATH_ROOT}/{parent}"),
+        None => ROUTE_PATH_ROOT.to_string(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{RangeSpec, parse_range};
-    use axum::http::HeaderValue;
+    use super::{
+        FilesGetQuery, ListingFormat, RangeSpec, create_dir, listing_format, move_entry,
+        parent_uri, parse_range, remove_entry, store_upload,
+    };
+    use crate::conflict::ConflictPolicy;
+    use crate::errors::ServerError;
+    use crate::upload::form_upload_root;
+    use axum::body::Body;
+    use axum::extract::{FromRequest, Multipart, Request};
+    use axum::http::header::{ACCEPT, CONTENT_TYPE};
+    use axum::http::{HeaderMap, HeaderValue};
+    use std::path::{Path as StdPath, PathBuf};
+    use tempfile::TempDir;
 
     fn satisfiable(start: u64, end_inclusive: u64) -> RangeSpec {
         RangeSpec::Satisfiable {
@@ -437,4 +1278,402 @@ mod tests {
         assert_eq!(parse("bytes=-1", 1), satisfiable(0, 0));
         assert_eq!(parse("bytes=1-1", 1), RangeSpec::Unsatisfiable);
     }
+
+    #[test]
+    fn parent_uri_of_top_level_entry_is_root() {
+        assert_eq!(parent_uri("file.txt"), "/files");
+        assert_eq!(parent_uri("dir/"), "/files");
+    }
+
+    #[test]
+    fn parent_uri_of_nested_entry() {
+        assert_eq!(parent_uri("a/b/file.txt"), "/files/a/b");
+        assert_eq!(parent_uri("a/b/"), "/files/a");
+    }
+
+    #[tokio::test]
+    async fn remove_entry_deletes_file() {
+        let dir = TempDir::new().unwrap();
+        let file_path = dir.path().join("a.txt");
+        std::fs::write(&file_path, b"x").unwrap();
+
+        remove_entry(&file_path, false).await.unwrap();
+
+        assert!(!file_path.exists());
+    }
+
+    #[tokio::test]
+    async fn remove_entry_deletes_empty_dir_without_recursive() {
+        let dir = TempDir::new().unwrap();
+        let sub = dir.path().join("empty");
+        std::fs::create_dir(&sub).unwrap();
+
+        remove_entry(&sub, false).await.unwrap();
+
+        assert!(!sub.exists());
+    }
+
+    #[tokio::test]
+    async fn remove_entry_refuses_non_empty_dir_without_recursive() {
+        let dir = TempDir::new().unwrap();
+        let sub = dir.path().join("full");
+        std::fs::create_dir(&sub).unwrap();
+        std::fs::write(sub.join("a.txt"), b"x").unwrap();
+
+        let result = remove_entry(&sub, false).await;
+
+        assert!(matches!(result, Err(ServerError::DirectoryIsNotEmpty)));
+        assert!(sub.join("a.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn remove_entry_deletes_non_empty_dir_with_recursive() {
+        let dir = TempDir::new().unwrap();
+        let sub = dir.path().join("full");
+        std::fs::create_dir_all(sub.join("nested")).unwrap();
+        std::fs::write(sub.join("nested").join("a.txt"), b"x").unwrap();
+
+        remove_entry(&sub, true).await.unwrap();
+
+        assert!(!sub.exists());
+    }
+
+    #[tokio::test]
+    async fn remove_entry_missing_path_is_not_found() {
+        let dir = TempDir::new().unwrap();
+
+        let result = remove_entry(&dir.path().join("nope"), false).await;
+
+        assert!(
+            matches!(result, Err(ServerError::IoError(ref err)) if err.kind() == std::io::ErrorKind::NotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn move_entry_renames_file() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("a.txt");
+        let destination = dir.path().join("b.txt");
+        std::fs::write(&source, b"a").unwrap();
+
+        move_entry(source.clone(), destination.clone(), false)
+            .await
+            .unwrap();
+
+        assert!(!source.exists());
+        assert_eq!(std::fs::read(&destination).unwrap(), b"a");
+    }
+
+    #[tokio::test]
+    async fn move_entry_refuses_existing_destination_without_overwrite() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("a.txt");
+        let destination = dir.path().join("b.txt");
+        std::fs::write(&source, b"a").unwrap();
+        std::fs::write(&destination, b"b").unwrap();
+
+        let result = move_entry(source.clone(), destination.clone(), false).await;
+
+        assert!(matches!(result, Err(ServerError::DestinationAlreadyExists)));
+        assert_eq!(std::fs::read(&source).unwrap(), b"a");
+        assert_eq!(std::fs::read(&destination).unwrap(), b"b");
+    }
+
+    #[tokio::test]
+    async fn move_entry_replaces_file_with_overwrite() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("a.txt");
+        let destination = dir.path().join("b.txt");
+        std::fs::write(&source, b"a").unwrap();
+        std::fs::write(&destination, b"b").unwrap();
+
+        move_entry(source.clone(), destination.clone(), true)
+            .await
+            .unwrap();
+
+        assert!(!source.exists());
+        assert_eq!(std::fs::read(&destination).unwrap(), b"a");
+    }
+
+    #[tokio::test]
+    async fn move_entry_replaces_non_empty_dir_with_overwrite() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("src");
+        let destination = dir.path().join("dst");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(source.join("new.txt"), b"new").unwrap();
+        std::fs::create_dir(&destination).unwrap();
+        std::fs::write(destination.join("old.txt"), b"old").unwrap();
+
+        move_entry(source.clone(), destination.clone(), true)
+            .await
+            .unwrap();
+
+        assert!(!source.exists());
+        assert!(destination.join("new.txt").exists());
+        assert!(!destination.join("old.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn move_entry_refuses_moving_dir_into_itself() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("src");
+        std::fs::create_dir(&source).unwrap();
+
+        let result = move_entry(source.clone(), source.join("nested"), false).await;
+
+        assert!(matches!(result, Err(ServerError::CannotMoveIntoItself)));
+        assert!(source.exists());
+    }
+
+    #[tokio::test]
+    async fn move_entry_refuses_replacing_a_parent() {
+        let dir = TempDir::new().unwrap();
+        let parent = dir.path().join("a");
+        let source = parent.join("b");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("keep.txt"), b"keep").unwrap();
+
+        let result = move_entry(source.clone(), parent.clone(), true).await;
+
+        assert!(matches!(result, Err(ServerError::CannotReplaceParent)));
+        assert_eq!(std::fs::read(source.join("keep.txt")).unwrap(), b"keep");
+    }
+
+    #[tokio::test]
+    async fn move_entry_refuses_replacing_an_entry_of_another_type() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("src");
+        let destination = dir.path().join("dst.txt");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(&destination, b"dst").unwrap();
+
+        let result = move_entry(source.clone(), destination.clone(), true).await;
+
+        assert!(matches!(result, Err(ServerError::DestinationTypeMismatch)));
+        assert!(source.is_dir());
+        assert_eq!(std::fs::read(&destination).unwrap(), b"dst");
+
+        let result = move_entry(destination.clone(), source.clone(), true).await;
+
+        assert!(matches!(result, Err(ServerError::DestinationTypeMismatch)));
+        assert!(source.is_dir());
+        assert!(destination.is_file());
+    }
+
+    #[tokio::test]
+    async fn move_entry_missing_source_is_not_found() {
+        let dir = TempDir::new().unwrap();
+
+        let result = move_entry(dir.path().join("nope"), dir.path().join("b"), false).await;
+
+        assert!(
+            matches!(result, Err(ServerError::IoError(ref err)) if err.kind() == std::io::ErrorKind::NotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn create_dir_creates_missing_parents() {
+        let dir = TempDir::new().unwrap();
+        let new_dir = dir.path().join("a").join("b").join("c");
+
+        create_dir(&new_dir).await.unwrap();
+
+        assert!(new_dir.is_dir());
+    }
+
+    #[tokio::test]
+    async fn create_dir_refuses_existing_dir() {
+        let dir = TempDir::new().unwrap();
+        let new_dir = dir.path().join("a");
+        std::fs::create_dir(&new_dir).unwrap();
+
+        let result = create_dir(&new_dir).await;
+
+        assert!(matches!(result, Err(ServerError::DirectoryAlreadyExists)));
+    }
+
+    #[tokio::test]
+    async fn create_dir_refuses_existing_file() {
+        let dir = TempDir::new().unwrap();
+        let file_path = dir.path().join("a");
+        std::fs::write(&file_path, b"x").unwrap();
+
+        let result = create_dir(&file_path).await;
+
+        assert!(matches!(result, Err(ServerError::DirectoryAlreadyExists)));
+        assert!(file_path.is_file());
+    }
+
+    /// Store the first field of a multipart body with a single `file` part.
+    async fn store_single_upload(
+        dir: &StdPath,
+        file_name: &str,
+        conflict: ConflictPolicy,
+    ) -> Result<String, ServerError> {
+        let body = format!(
+            "--X\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{file_name}\"\r\n\r\nhello\r\n--X--\r\n"
+        );
+        let request = Request::builder()
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=X")
+            .body(Body::from(body))
+            .unwrap();
+        let mut multipart = Multipart::from_request(request, &()).await.unwrap();
+        let field = multipart.next_field().await.unwrap().unwrap();
+        store_upload(dir, file_name, conflict, &temp_dir(dir), field).await
+    }
+
+    fn temp_dir(dir: &StdPath) -> PathBuf {
+        form_upload_root(dir)
+    }
+
+    /// Whether no temporary file of an upload was left behind.
+    fn no_temp_files(dir: &StdPath) -> bool {
+        std::fs::read_dir(temp_dir(dir)).is_ok_and(|mut entries| entries.next().is_none())
+    }
+
+    #[tokio::test]
+    async fn store_upload_recreates_relative_dirs() {
+        let dir = TempDir::new().unwrap();
+
+        let stored_name =
+            store_single_upload(dir.path(), "photos/2024/a.txt", ConflictPolicy::Reject)
+                .await
+                .unwrap();
+
+        assert_eq!(stored_name, "photos/2024/a.txt");
+        let file_path = dir.path().join("photos").join("2024").join("a.txt");
+        assert_eq!(std::fs::read(&file_path).unwrap(), b"hello");
+        assert!(no_temp_files(dir.path()));
+    }
+
+    #[tokio::test]
+    async fn store_upload_rejects_escaping_names() {
+        let dir = TempDir::new().unwrap();
+        let store = dir.path().join("store");
+        std::fs::create_dir(&store).unwrap();
+
+        let result = store_single_upload(&store, "../escape.txt", ConflictPolicy::Overwrite).await;
+
+        assert!(matches!(result, Err(ServerError::InvalidPath)));
+        assert!(!dir.path().join("escape.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn store_upload_applies_conflict_policy() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"old").unwrap();
+
+        let rejected = store_single_upload(dir.path(), "a.txt", ConflictPolicy::Reject).await;
+        let renamed = store_single_upload(dir.path(), "a.txt", ConflictPolicy::AutoRename)
+            .await
+            .unwrap();
+
+        assert!(matches!(rejected, Err(ServerError::FileConflict)));
+        assert!(no_temp_files(dir.path()));
+        assert_eq!(std::fs::read(dir.path().join("a.txt")).unwrap(), b"old");
+        assert_eq!(renamed, "a (1).txt");
+        assert_eq!(
+            std::fs::read(dir.path().join("a (1).txt")).unwrap(),
+            b"hello"
+        );
+    }
+
+    #[tokio::test]
+    async fn store_upload_accepts_raw_body() {
+        let dir = TempDir::new().unwrap();
+        let body = Body::from("raw bytes").into_data_stream();
+
+        let stored_name = store_upload(
+            dir.path(),
+            "ci/build.log",
+            ConflictPolicy::Reject,
+            &temp_dir(dir.path()),
+            body,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(stored_name, "ci/build.log");
+        assert_eq!(
+            std::fs::read(dir.path().join("ci/build.log")).unwrap(),
+            b"raw bytes"
+        );
+        assert!(no_temp_files(dir.path()));
+    }
+
+    fn accept(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn listing_format_defaults_to_html() {
+        let query = FilesGetQuery::default();
+        assert_eq!(
+            listing_format(&HeaderMap::new(), query.format),
+            ListingFormat::Html
+        );
+        assert_eq!(
+            listing_format(
+                &accept("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+                query.format
+            ),
+            ListingFormat::Html
+        );
+    }
+
+    #[test]
+    fn listing_format_honours_accept_json() {
+        let query = FilesGetQuery::default();
+        assert_eq!(
+            listing_format(&accept("application/json"), query.format),
+            ListingFormat::Json
+        );
+        assert_eq!(
+            listing_format(&accept("text/plain, application/json; q=0.5"), query.format),
+            ListingFormat::Json
+        );
+    }
+
+    #[test]
+    fn listing_format_ignores_json_with_zero_quality() {
+        let query = FilesGetQuery::default();
+        assert_eq!(
+            listing_format(&accept("application/json;q=0"), query.format),
+            ListingFormat::Html
+        );
+        assert_eq!(
+            listing_format(
+                &accept("text/html, application/json; Q=0.000"),
+                query.format
+            ),
+            ListingFormat::Html
+        );
+        assert_eq!(
+            listing_format(&accept("application/json;q=0.001"), query.format),
+            ListingFormat::Json
+        );
+    }
+
+    #[test]
+    fn listing_format_query_overrides_accept() {
+        let json = FilesGetQuery {
+            format: Some(ListingFormat::Json),
+            ..Default::default()
+        };
+        let html = FilesGetQuery {
+            format: Some(ListingFormat::Html),
+            ..Default::default()
+        };
+        assert_eq!(
+            listing_format(&HeaderMap::new(), json.format),
+            ListingFormat::Json
+        );
+        assert_eq!(
+            listing_format(&accept("application/json"), html.format),
+            ListingFormat::Html
+        );
+    }
 }
@@ -0,0 +1,64 @@
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::path::PathBuf;
+
+use rustix::fs::{CWD, RenameFlags, renameat_with};
+use rustix::io::Errno;
+use tokio::task::spawn_blocking;
+
+/// Rename `from` to `to`, failing with [`IoErrorKind::AlreadyExists`] instead
+/// of replacing `to` when it already exists. On Linux this is a single atomic
+/// `renameat2(RENAME_NOREPLACE)`; filesystems that don't support the flag fall
+/// back to an existence check followed by a plain rename.
+pub async fn rename_no_replace(from: PathBuf, to: PathBuf) -> Result<(), IoError> {
+    spawn_blocking(
+        move || match renameat_with(CWD, &from, CWD, &to, RenameFlags::NOREPLACE) {
+            Ok(()) => Ok(()),
+
+            Err(Errno::INVAL | Errno::NOSYS) => {
+                if to.try_exists()? {
+                    return Err(IoErrorKind::AlreadyExists.into());
+                }
+                std::fs::rename(&from, &to)
+            }
+
+            Err(errno) => Err(errno.into()),
+        },
+    )
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn rename_no_replace_moves_to_free_destination() {
+        let dir = TempDir::new().unwrap();
+        let from = dir.path().join("a.txt");
+        let to = dir.path().join("b.txt");
+        std::fs::write(&from, b"a").unwrap();
+
+        rename_no_replace(from.clone(), to.clone()).await.unwrap();
+
+        assert!(!from.exists());
+        assert_eq!(std::fs::read(&to).unwrap(), b"a");
+    }
+
+    #[tokio::test]
+    async fn rename_no_replace_refuses_existing_destination() {
+        let dir = TempDir::new().unwrap();
+        let from = dir.path().join("a.txt");
+        let to = dir.path().join("b.txt");
+        std::fs::write(&from, b"a").unwrap();
+        std::fs::write(&to, b"b").unwrap();
+
+        let err = rename_no_replace(from.clone(), to.clone())
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.kind(), IoErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(&from).unwrap(), b"a");
+        assert_eq!(std::fs::read(&to).unwrap(), b"b");
+    }
+}
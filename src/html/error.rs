@@ -48,3 +48,36 @@ pub fn bad_request<T: AsRef<str>>(message: T) -> String {
     .render_once()
     .unwrap()
 }
+
+pub fn conflict<T: AsRef<str>>(message: T) -> String {
+    templates::Error {
+        status: StatusCode::CONFLICT,
+        title: Some("Conflict"),
+        message: message.as_ref(),
+        ..Default::default()
+    }
+    .render_once()
+    .unwrap()
+}
+
+pub fn forbidden<T: AsRef<str>>(message: T) -> String {
+    templates::Error {
+        status: StatusCode::FORBIDDEN,
+        title: Some("Forbidden"),
+        message: message.as_ref(),
+        ..Default::default()
+    }
+    .render_once()
+    .unwrap()
+}
+
+pub fn share_not_found() -> String {
+    templates::Error {
+        status: StatusCode::NOT_FOUND,
+        title: Some("Link Not Found"),
+        message: "This link doesn't exist, has expired or can't be used anymore.",
+        ..Default::default()
+    }
+    .render_once()
+    .unwrap()
+}
@@ -1,5 +1,3 @@
-use std::time::SystemTime;
-
 use axum::extract::{Form, Query};
 use axum::http::{StatusCode, Uri, header::SET_COOKIE};
 use axum::response::{Html, IntoResponse};
@@ -7,7 +5,7 @@ use jsonwebtoken::{Header, encode};
 use sailfish::TemplateOnce;
 use serde::Deserialize;
 
-use crate::auth::{Claims, KEYS};
+use crate::auth::{Claims, KEYS, verify_credentials};
 use crate::config::CONFIG;
 use crate::errors::ServerError;
 use crate::templates;
@@ -38,15 +36,8 @@ pub async fn post(
     uri: Uri,
     Form(login_form): Form<LoginForm>,
 ) -> Result<impl IntoResponse, ServerError> {
-    if login_form.username == CONFIG.username && login_form.password == CONFIG.password {
-        let claims = Claims {
-            sub: login_form.username,
-            exp: SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_secs()
-                + CONFIG.token_expiry,
-        };
+    if verify_credentials(&login_form.username, &login_form.password).await? {
+        let claims = Claims::new(login_form.username);
         let token = encode(&Header::default(), &claims, &KEYS.encoding).map_err(|_| {
             ServerError::TokenCreation {
                 redirect_uri: query.redirect.clone(),
@@ -1,7 +1,10 @@
+use std::time::Duration;
+
 use anyhow::Result;
 use axum::extract::{DefaultBodyLimit, Request};
 use axum::http::{StatusCode, Uri};
-use axum::{Router, response::Html, routing::get, serve};
+use axum::routing::{get, options, post};
+use axum::{Router, middleware, response::Html, serve};
 use hyper::body::Incoming;
 use hyper::service::service_fn as hyper_service_fn;
 use hyper_util::rt::{TokioExecutor, TokioIo};
@@ -9,18 +12,30 @@ use tokio::spawn;
 use tokio_rustls::TlsAcceptor;
 use tower_service::Service;
 
+mod acl;
+mod archive;
 mod assets;
 mod auth;
+mod chunk_map;
 mod config;
+mod conflict;
 mod errors;
+mod file_hash;
 mod files;
+mod fs_utils;
 mod home;
 mod html;
 mod login;
 mod ping;
+mod role;
 mod safe_path;
+mod share;
 mod templates;
+mod token;
+mod tus;
 mod upload;
+mod uploads;
+mod users;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -28,6 +43,8 @@ async fn main() -> Result<()> {
         .route(assets::ROUTE_PATH, get(assets::get))
         .route(ping::ROUTE_PATH, get(ping::get))
         .route(login::ROUTE_PATH, get(login::get).post(login::post))
+        .route(token::ROUTE_PATH, post(token::post))
+        .route(share::ROUTE_PATH, get(share::get).post(share::post))
         .route(home::ROUTE_PATH, get(home::get))
         .route(files::ROUTE_PATH_ROOT, get(files::root_get))
         .route(
@@ -40,6 +57,8 @@ async fn main() -> Result<()> {
             files::ROUTE_PATH,
             get(files::get)
                 .post(files::post)
+                .put(files::put)
+                .delete(files::delete)
                 .layer(DefaultBodyLimit::disable()),
         )
         .route(
@@ -47,8 +66,23 @@ async fn main() -> Result<()> {
             get(upload::get)
                 .post(upload::post)
                 .put(upload::put)
+                .delete(upload::delete)
                 .layer(DefaultBodyLimit::disable()),
         )
+        .route(uploads::ROUTE_PATH, get(uploads::get))
+        .merge(
+            Router::new()
+                .route(tus::ROUTE_PATH_ROOT, options(tus::options).post(tus::post))
+                .route(
+                    tus::ROUTE_PATH,
+                    options(tus::options)
+                        .head(tus::head)
+                        .patch(tus::patch)
+                        .delete(tus::delete)
+                        .layer(DefaultBodyLimit::disable()),
+                )
+                .layer(middleware::from_fn(tus::tus_resumable)),
+        )
         .fallback(async move |uri: Uri| {
             (
                 StatusCode::NOT_FOUND,
@@ -58,9 +92,30 @@ async fn main() -> Result<()> {
             )
         });
 
+    let upload_ttl = Duration::from_secs(config::CONFIG.upload_ttl);
+
+    upload::migrate_legacy_uploads(&config::CONFIG.store_path).await;
+
+    if config::CONFIG.sweep_uploads {
+        let removed =
+            upload::sweep_expired_uploads_in_store(&config::CONFIG.store_path, upload_ttl).await;
+        println!("Removed {} expired upload(s)", removed.len());
+        let removed = share::sweep_ended_shares(&config::CONFIG.store_path).await;
+        println!("Removed {} ended share link(s)", removed.len());
+        return Ok(());
+    }
+
+    config::CONFIG.users.create_roots()?;
+
     // Recover any chunks left in `Ongoing` from a hard-killed previous run.
     // Runs before the listener is bound so concurrent uploads cannot race the cleanup.
-    upload::reset_stale_ongoing_chunks(&config::CONFIG.store_path).await;
+    upload::reset_stale_ongoing_chunks(&upload::staging_root(&config::CONFIG.store_path)).await;
+    upload::clear_form_uploads(&config::CONFIG.store_path).await;
+
+    spawn(upload::sweep_expired_periodically(
+        config::CONFIG.store_path.clone(),
+        upload_ttl,
+    ));
 
     let listener = tokio::net::TcpListener::bind(config::CONFIG.listen).await?;
 
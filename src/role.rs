@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+/// Something a user may be allowed to do, see [`Role::can`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capability {
+    /// Browse directories and download files.
+    Read,
+    /// Upload files and create directories.
+    Write,
+    /// Delete, move and overwrite files and directories.
+    Delete,
+    /// Manage the server, e.g. see every upload in progress.
+    Admin,
+}
+
+/// The role of a user, which decides their capabilities.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Can only browse and download.
+    Viewer,
+    /// Can only upload, without seeing what is stored: a drop box.
+    Uploader,
+    /// Can read, upload, and change what is stored.
+    Editor,
+    /// Can do everything. Users without a role are admins, as every user was
+    /// before roles existed.
+    #[default]
+    Admin,
+}
+impl Role {
+    pub fn can(self, capability: Capability) -> bool {
+        match self {
+            Self::Viewer => capability == Capability::Read,
+            Self::Uploader => capability == Capability::Write,
+            Self::Editor => capability != Capability::Admin,
+            Self::Admin => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Capability::*;
+
+    fn capabilities(role: Role) -> Vec<Capability> {
+        [Read, Write, Delete, Admin]
+            .into_iter()
+            .filter(|&capability| role.can(capability))
+            .collect()
+    }
+
+    #[test]
+    fn roles_grant_their_capabilities() {
+        assert_eq!(capabilities(Role::Viewer), [Read]);
+        assert_eq!(capabilities(Role::Uploader), [Write]);
+        assert_eq!(capabilities(Role::Editor), [Read, Write, Delete]);
+        assert_eq!(capabilities(Role::Admin), [Read, Write, Delete, Admin]);
+    }
+
+    #[test]
+    fn parses_roles_in_kebab_case() {
+        assert_eq!(
+            serde_json::from_str::<Role>(r#""uploader""#).unwrap(),
+            Role::Uploader
+        );
+        assert!(serde_json::from_str::<Role>(r#""Uploader""#).is_err());
+    }
+}
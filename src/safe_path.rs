@@ -2,15 +2,22 @@ use std::path::{Component, Path, PathBuf};
 
 use crate::errors::ServerError;
 
+/// Name of the directory in the store root holding the server's own state,
+/// e.g. staged uploads. It's reserved at every depth, so no user path can
+/// name it however it is assembled from a base and a relative part.
+pub const STATE_DIR_NAME: &str = ".sfs";
+
 /// Join `user_path` onto `base`, rejecting any user input that could escape `base`.
 /// Only `Normal` components are allowed — `..`, `.`, absolute roots, and Windows
-/// path prefixes are refused. We don't use `canonicalize` because the target may
-/// not exist yet (e.g. when creating a new upload).
+/// path prefixes are refused, and so is the reserved [`STATE_DIR_NAME`]. We don't
+/// use `canonicalize` because the target may not exist yet (e.g. when creating a
+/// new upload).
 pub fn safe_join(base: &Path, user_path: &str) -> Result<PathBuf, ServerError> {
     let user_path = Path::new(user_path);
     for component in user_path.components() {
-        if !matches!(component, Component::Normal(_)) {
-            return Err(ServerError::InvalidPath);
+        match component {
+            Component::Normal(name) if name != STATE_DIR_NAME => {}
+            _ => return Err(ServerError::InvalidPath),
         }
     }
     Ok(base.join(user_path))
@@ -117,6 +124,14 @@ mod tests {
         assert_rejects("//etc/hostname");
     }
 
+    #[test]
+    fn rejects_state_dir() {
+        assert_rejects(".sfs");
+        assert_rejects(".sfs/uploads");
+        assert_rejects("a/.sfs/b");
+        assert_accepts(".sfs-notes", "/store/.sfs-notes");
+    }
+
     #[cfg(windows)]
     #[test]
     fn rejects_windows_drive_prefix() {
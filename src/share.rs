@@ -0,0 +1,693 @@
+use std::fs::{File as StdFile, Metadata, OpenOptions};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Seek, SeekFrom, Write};
+use std::os::fd::AsFd;
+use std::path::{Path as StdPath, PathBuf};
+
+use axum::extract::{Form, Path, Query};
+use axum::http::header::WWW_AUTHENTICATE;
+use axum::http::{HeaderMap, Method, StatusCode};
+use axum::response::{Html, IntoResponse, Json, Response};
+use axum_extra::headers::authorization::Basic;
+use axum_extra::headers::{Authorization, HeaderMapExt};
+use rand::{RngExt, rng};
+use rustix::fs::{FlockOperation, flock};
+use sailfish::TemplateOnce;
+use serde::de::{Deserializer, Error as _};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use time::{Duration, OffsetDateTime};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::task::spawn_blocking;
+
+use crate::archive::ArchiveFormat;
+use crate::auth::{BASIC_AUTH_CHALLENGE, Claims, is_browser};
+use crate::config::CONFIG;
+use crate::errors::ServerError;
+use crate::files;
+use crate::role::Capability;
+use crate::safe_path::{STATE_DIR_NAME, safe_join};
+use crate::templates;
+use crate::upload::is_upload_artifact;
+use crate::users::{hash_password, verify_password};
+
+pub const ROUTE_PATH: &str = "/s/{token}";
+pub const ROUTE_PATH_ROOT: &str = "/s";
+
+/// How long a share link works when its creator doesn't say.
+const DEFAULT_EXPIRY: u64 = 7 * 24 * 60 * 60;
+/// The longest a share link can work.
+const MAX_EXPIRY: u64 = 365 * 24 * 60 * 60;
+
+/// The directory holding a record for every share link, named after its token.
+pub fn shares_root(store_path: &StdPath) -> PathBuf {
+    store_path.join(STATE_DIR_NAME).join("shares")
+}
+
+fn record_path(shares_root: &StdPath, token: &str) -> PathBuf {
+    shares_root.join(format!("{token}.json"))
+}
+
+/// Tokens are 32 random bytes in lowercase hex, so anything else can't be one.
+fn is_token(token: &str) -> bool {
+    token.len() == 64
+        && token
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+}
+
+fn new_token() -> String {
+    rng()
+        .random::<[u8; 32]>()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// The record of a share link.
+#[derive(Debug, Serialize, Deserialize)]
+struct Share {
+    /// The shared file or directory, relative to the store root.
+    path: String,
+
+    /// The user who created the link. It only works as long as they could
+    /// still download what it shares themselves.
+    created_by: String,
+
+    #[serde(with = "time::serde::rfc3339")]
+    expires_at: OffsetDateTime,
+
+    /// The argon2 hash of the password the link asks for, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password_hash: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_downloads: Option<u64>,
+
+    /// How often the link was downloaded. Every request for its content
+    /// counts, including ones resuming an interrupted download.
+    #[serde(default)]
+    downloads: u64,
+}
+impl Share {
+    /// Whether the link stopped working, because it expired or was downloaded
+    /// as often as it allows.
+    fn has_ended(&self) -> bool {
+        self.expires_at <= OffsetDateTime::now_utc()
+            || self
+                .max_downloads
+                .is_some_and(|max_downloads| self.downloads >= max_downloads)
+    }
+
+    /// The name the shared file or directory is downloaded as.
+    fn name(&self) -> String {
+        match self.path.rsplit('/').next() {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => "files".to_string(),
+        }
+    }
+}
+
+/// The options of a new share link, from a form or JSON.
+#[derive(Deserialize, Default)]
+pub struct ShareForm {
+    /// Seconds until the link expires, a week by default and a year at most.
+    #[serde(default, deserialize_with = "optional_number")]
+    expires_in: Option<u64>,
+
+    /// A password the link asks for before downloading.
+    #[serde(default)]
+    password: Option<String>,
+
+    /// How often the link can be downloaded.
+    #[serde(default, deserialize_with = "optional_number")]
+    max_downloads: Option<u64>,
+}
+
+/// An optional number, which forms send as a string that is empty when the
+/// field was left blank.
+fn optional_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Field {
+        Number(u64),
+        Text(String),
+    }
+
+    match Option::<Field>::deserialize(deserializer)? {
+        Some(Field::Number(number)) => Ok(Some(number)),
+        Some(Field::Text(text)) if !text.is_empty() => {
+            text.parse().map(Some).map_err(D::Error::custom)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// A share link that was just created.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedShare {
+    /// The path of the link, e.g. `/s/3f2a…`.
+    pub url: String,
+
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_downloads: Option<u64>,
+}
+
+/// Create a share link for the file or directory at `full_path`, which the
+/// user has to be allowed to download completely.
+pub async fn create(
+    claims: &Claims,
+    full_path: &StdPath,
+    form: ShareForm,
+) -> Result<CreatedShare, ServerError> {
+    if is_upload_artifact(full_path).await {
+        return Err(IoError::from(IoErrorKind::NotFound).into());
+    }
+    if fs::metadata(full_path).await?.is_dir() {
+        claims.authorize_tree(Capability::Read, full_path)?;
+    } else {
+        claims.authorize(Capability::Read, full_path)?;
+    }
+    let path = full_path
+        .strip_prefix(&CONFIG.store_path)
+        .ok()
+        .and_then(|path| path.to_str())
+        .ok_or(ServerError::InvalidPath)?
+        .to_string();
+
+    if form.expires_in == Some(0) || form.max_downloads == Some(0) {
+        return Err(ServerError::InvalidShareOptions);
+    }
+    let password_hash = match form.password.filter(|password| !password.is_empty()) {
+        Some(password) => Some(
+            spawn_blocking(move || hash_password(&password))
+                .await?
+                .map_err(|err| ServerError::InternalError(err.to_string()))?,
+        ),
+        None => None,
+    };
+    let expires_in = form.expires_in.unwrap_or(DEFAULT_EXPIRY).min(MAX_EXPIRY);
+    let share = Share {
+        path,
+        created_by: claims.sub.clone(),
+        expires_at: OffsetDateTime::now_utc() + Duration::seconds(expires_in as i64),
+        password_hash,
+        max_downloads: form.max_downloads,
+        downloads: 0,
+    };
+    let content = serde_json::to_vec(&share).map_err(|err| ServerError::Custom {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        message: err.to_string(),
+    })?;
+
+    let token = new_token();
+    let shares_root = shares_root(&CONFIG.store_path);
+    fs::create_dir_all(&shares_root).await?;
+    let mut record = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(record_path(&shares_root, &token))
+        .await?;
+    record.write_all(&content).await?;
+
+    Ok(CreatedShare {
+        url: format!("{ROUTE_PATH_ROOT}/{token}"),
+        expires_at: share.expires_at,
+        max_downloads: share.max_downloads,
+    })
+}
+
+#[derive(Deserialize, Default)]
+pub struct ShareQuery {
+    /// The archive format a shared directory is downloaded in, zip by default.
+    archive: Option<ArchiveFormat>,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordForm {
+    password: String,
+}
+
+/// Download what a share link shares, without logging in. Protected links
+/// also take their password as the password of HTTP Basic credentials, with
+/// any username.
+pub async fn get(
+    Path(token): Path<String>,
+    Query(query): Query<ShareQuery>,
+    method: Method,
+    headers: HeaderMap,
+) -> Result<Response, ServerError> {
+    let password = headers
+        .typed_get::<Authorization<Basic>>()
+        .map(|Authorization(basic)| basic.password().to_string());
+    serve(
+        &token,
+        password,
+        query,
+        counts_as_download(&method),
+        headers,
+    )
+    .await
+}
+
+/// Download what a protected share link shares, with the password submitted
+/// on its password page.
+pub async fn post(
+    Path(token): Path<String>,
+    Query(query): Query<ShareQuery>,
+    headers: HeaderMap,
+    Form(form): Form<PasswordForm>,
+) -> Result<Response, ServerError> {
+    serve(&token, Some(form.password), query, true, headers).await
+}
+
+/// Whether a request for a share link counts towards its maximum downloads.
+/// Every request for its content does, ranged or not: which of them resume a
+/// download that was counted already can't be told apart from new ones.
+fn counts_as_download(method: &Method) -> bool {
+    method != Method::HEAD
+}
+
+async fn serve(
+    token: &str,
+    password: Option<String>,
+    query: ShareQuery,
+    counts: bool,
+    headers: HeaderMap,
+) -> Result<Response, ServerError> {
+    if !is_token(token) {
+        return Err(ServerError::ShareNotFound);
+    }
+    let record_path = record_path(&shares_root(&CONFIG.store_path), token);
+    let access = spawn_blocking(move || {
+        open_share(
+            &record_path,
+            &CONFIG.store_path,
+            password.as_deref(),
+            |share, full_path, metadata| {
+                if !creator_can_download(share, full_path, metadata) {
+                    return Err(ServerError::ShareNotFound);
+                }
+                Ok(counts)
+            },
+        )
+    })
+    .await??;
+
+    match access {
+        Access::Granted {
+            full_path,
+            metadata,
+            name,
+        } if metadata.is_dir() => Ok(files::archive_response(
+            full_path,
+            name,
+            query.archive.unwrap_or(ArchiveFormat::Zip),
+        )),
+        Access::Granted {
+            full_path,
+            metadata,
+            ..
+        } => files::serve_file(&full_path, metadata.len(), &headers).await,
+        Access::PasswordRequired { name, wrong } if is_browser(&headers) => Ok((
+            StatusCode::UNAUTHORIZED,
+            Html(
+                templates::Share {
+                    url: &format!("{ROUTE_PATH_ROOT}/{token}"),
+                    name: &name,
+                    wrong_password: wrong,
+                }
+                .render_once()?,
+            ),
+        )
+            .into_response()),
+        Access::PasswordRequired { wrong, .. } => Ok((
+            StatusCode::UNAUTHORIZED,
+            [(WWW_AUTHENTICATE, BASIC_AUTH_CHALLENGE)],
+            Json(json!({
+                "error": if wrong { "Incorrect password." } else { "This link requires a password." }
+            })),
+        )
+            .into_response()),
+    }
+}
+
+/// Whether the creator of `share` still exists and could download it.
+fn creator_can_download(share: &Share, full_path: &StdPath, metadata: &Metadata) -> bool {
+    let Some(root) = CONFIG.users.root(&share.created_by) else {
+        return false;
+    };
+    let claims = Claims::new(share.created_by.clone());
+    full_path.starts_with(root)
+        && if metadata.is_dir() {
+            claims.can_at_tree(Capability::Read, full_path)
+        } else {
+            claims.can_at(Capability::Read, full_path)
+        }
+}
+
+#[derive(Debug)]
+enum Access {
+    Granted {
+        full_path: PathBuf,
+        metadata: Metadata,
+        name: String,
+    },
+    PasswordRequired {
+        name: String,
+        /// Whether a password was given, but it was wrong.
+        wrong: bool,
+    },
+}
+
+/// Check whether the share recorded at `record_path` can be downloaded with
+/// `password`. `check` decides whether the creator still allows the download
+/// and whether it counts towards the maximum, which is enforced and updated
+/// while holding a lock on the record, so concurrent downloads can't exceed
+/// it. Records of links that have ended are removed.
+fn open_share(
+    record_path: &StdPath,
+    store_path: &StdPath,
+    password: Option<&str>,
+    check: impl FnOnce(&Share, &StdPath, &Metadata) -> Result<bool, ServerError>,
+) -> Result<Access, ServerError> {
+    let mut record = match OpenOptions::new().read(true).write(true).open(record_path) {
+        Ok(record) => record,
+        Err(err) if err.kind() == IoErrorKind::NotFound => return Err(ServerError::ShareNotFound),
+        Err(err) => return Err(err.into()),
+    };
+    flock(record.as_fd(), FlockOperation::LockExclusive)?;
+
+    let mut share = read_share(&mut record).ok_or(ServerError::ShareNotFound)?;
+    if share.has_ended() {
+        std::fs::remove_file(record_path)?;
+        return Err(ServerError::ShareNotFound);
+    }
+
+    if let Some(password_hash) = &share.password_hash
+        && !password.is_some_and(|password| verify_password(password, password_hash))
+    {
+        return Ok(Access::PasswordRequired {
+            name: share.name(),
+            wrong: password.is_some(),
+        });
+    }
+
+    let full_path = safe_join(store_path, &share.path)?;
+    let metadata = match std::fs::metadata(&full_path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == IoErrorKind::NotFound => return Err(ServerError::ShareNotFound),
+        Err(err) => return Err(err.into()),
+    };
+    if check(&share, &full_path, &metadata)? {
+        share.downloads += 1;
+        write_share(&mut record, &share)?;
+    }
+
+    Ok(Access::Granted {
+        full_path,
+        metadata,
+        name: share.name(),
+    })
+}
+
+/// Remove the records under `shares_root` of links that have ended, and return
+/// their paths. Failures are logged and skipped.
+fn sweep_ended_shares_sync(shares_root: &StdPath) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(shares_root) else {
+        return Vec::new();
+    };
+
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|record_path| {
+            let Ok(mut record) = StdFile::open(record_path) else {
+                return false;
+            };
+            // Wait for downloads that are being counted.
+            if flock(record.as_fd(), FlockOperation::LockExclusive).is_err()
+                || !read_share(&mut record).is_some_and(|share| share.has_ended())
+            {
+                return false;
+            }
+
+            match std::fs::remove_file(record_path) {
+                Ok(()) => {
+                    eprintln!("share sweep: removed ended link {}", record_path.display());
+                    true
+                }
+                // Removed by a download of the link in the meantime.
+                Err(err) if err.kind() == IoErrorKind::NotFound => false,
+                Err(err) => {
+                    eprintln!(
+                        "share sweep: failed to remove {}: {err}",
+                        record_path.display()
+                    );
+                    false
+                }
+            }
+        })
+        .collect()
+}
+
+/// See [`sweep_ended_shares_sync`].
+pub async fn sweep_ended_shares(store_path: &StdPath) -> Vec<PathBuf> {
+    let shares_root = shares_root(store_path);
+    spawn_blocking(move || sweep_ended_shares_sync(&shares_root))
+        .await
+        .unwrap_or_default()
+}
+
+fn read_share(record: &mut StdFile) -> Option<Share> {
+    let mut content = Vec::new();
+    record.read_to_end(&mut content).ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+fn write_share(record: &mut StdFile, share: &Share) -> Result<(), ServerError> {
+    let content = serde_json::to_vec(share).map_err(|err| ServerError::Custom {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        message: err.to_string(),
+    })?;
+    record.set_len(0)?;
+    record.seek(SeekFrom::Start(0))?;
+    record.write_all(&content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::FromRequest;
+    use tempfile::TempDir;
+
+    fn share(path: &str) -> Share {
+        Share {
+            path: path.to_string(),
+            created_by: "alice".to_string(),
+            expires_at: OffsetDateTime::now_utc() + Duration::hours(1),
+            password_hash: None,
+            max_downloads: None,
+            downloads: 0,
+        }
+    }
+
+    /// A store with `report.pdf` and the record of `share` for it.
+    fn store_with(share: &Share) -> (TempDir, PathBuf) {
+        let store = TempDir::new().unwrap();
+        std::fs::write(store.path().join("report.pdf"), "report").unwrap();
+        let shares_root = shares_root(store.path());
+        std::fs::create_dir_all(&shares_root).unwrap();
+        let record_path = record_path(&shares_root, &new_token());
+        std::fs::write(&record_path, serde_json::to_vec(share).unwrap()).unwrap();
+        (store, record_path)
+    }
+
+    fn open(
+        record_path: &StdPath,
+        store: &TempDir,
+        password: Option<&str>,
+        counts: bool,
+    ) -> Result<Access, ServerError> {
+        open_share(record_path, store.path(), password, |_, _, _| Ok(counts))
+    }
+
+    fn downloads(record_path: &StdPath) -> u64 {
+        serde_json::from_slice::<Share>(&std::fs::read(record_path).unwrap())
+            .unwrap()
+            .downloads
+    }
+
+    #[test]
+    fn tokens_are_random_hex() {
+        let token = new_token();
+
+        assert!(is_token(&token));
+        assert_ne!(token, new_token());
+        assert!(!is_token(&token.to_uppercase()));
+        assert!(!is_token(&token[1..]));
+        assert!(!is_token("../../users.toml"));
+    }
+
+    #[test]
+    fn grants_access_to_the_shared_file() {
+        let (store, record_path) = store_with(&share("report.pdf"));
+
+        match open(&record_path, &store, None, true).unwrap() {
+            Access::Granted {
+                full_path, name, ..
+            } => {
+                assert_eq!(full_path, store.path().join("report.pdf"));
+                assert_eq!(name, "report.pdf");
+            }
+            access => panic!("{access:?}"),
+        }
+        assert_eq!(downloads(&record_path), 1);
+    }
+
+    #[test]
+    fn removes_expired_shares() {
+        let mut expired = share("report.pdf");
+        expired.expires_at = OffsetDateTime::now_utc() - Duration::seconds(1);
+        let (store, record_path) = store_with(&expired);
+
+        assert!(matches!(
+            open(&record_path, &store, None, true),
+            Err(ServerError::ShareNotFound)
+        ));
+        assert!(!record_path.exists());
+    }
+
+    #[test]
+    fn asks_for_the_password() {
+        let mut protected = share("report.pdf");
+        protected.password_hash = Some(hash_password("secret").unwrap());
+        let (store, record_path) = store_with(&protected);
+
+        assert!(matches!(
+            open(&record_path, &store, None, true),
+            Ok(Access::PasswordRequired { wrong: false, .. })
+        ));
+        assert!(matches!(
+            open(&record_path, &store, Some("guess"), true),
+            Ok(Access::PasswordRequired { wrong: true, .. })
+        ));
+        assert_eq!(downloads(&record_path), 0);
+        assert!(matches!(
+            open(&record_path, &store, Some("secret"), true),
+            Ok(Access::Granted { .. })
+        ));
+    }
+
+    #[test]
+    fn limits_counted_downloads() {
+        let mut limited = share("report.pdf");
+        limited.max_downloads = Some(2);
+        let (store, record_path) = store_with(&limited);
+
+        assert!(open(&record_path, &store, None, true).is_ok());
+        // `HEAD` requests don't count.
+        assert!(open(&record_path, &store, None, false).is_ok());
+        assert_eq!(downloads(&record_path), 1);
+        assert!(open(&record_path, &store, None, true).is_ok());
+        assert_eq!(downloads(&record_path), 2);
+
+        // Once used up, every request is refused, even ones that wouldn't count.
+        assert!(matches!(
+            open(&record_path, &store, None, false),
+            Err(ServerError::ShareNotFound)
+        ));
+        assert!(!record_path.exists());
+    }
+
+    #[test]
+    fn ranged_downloads_count_and_are_refused_once_used_up() {
+        let mut limited = share("report.pdf");
+        limited.max_downloads = Some(1);
+        let (store, record_path) = store_with(&limited);
+        // Resuming is a `GET` with a `Range` header like any other.
+        let open_ranged = || {
+            open_share(&record_path, store.path(), None, |_, _, _| {
+                Ok(counts_as_download(&Method::GET))
+            })
+        };
+
+        assert!(open_ranged().is_ok());
+        assert_eq!(downloads(&record_path), 1);
+        assert!(matches!(open_ranged(), Err(ServerError::ShareNotFound)));
+    }
+
+    #[tokio::test]
+    async fn sweeps_only_ended_shares() {
+        let (store, active) = store_with(&share("report.pdf"));
+        let shares_root = shares_root(store.path());
+        let mut expired = share("report.pdf");
+        expired.expires_at = OffsetDateTime::now_utc() - Duration::seconds(1);
+        let expired_path = record_path(&shares_root, &new_token());
+        std::fs::write(&expired_path, serde_json::to_vec(&expired).unwrap()).unwrap();
+        let mut used_up = share("report.pdf");
+        used_up.max_downloads = Some(1);
+        used_up.downloads = 1;
+        let used_up_path = record_path(&shares_root, &new_token());
+        std::fs::write(&used_up_path, serde_json::to_vec(&used_up).unwrap()).unwrap();
+
+        let mut removed = sweep_ended_shares(store.path()).await;
+        removed.sort();
+        let mut expected = vec![expired_path, used_up_path];
+        expected.sort();
+
+        assert_eq!(removed, expected);
+        assert!(active.exists());
+    }
+
+    #[test]
+    fn refuses_shares_of_missing_or_unreadable_files() {
+        let (store, record_path) = store_with(&share("gone.pdf"));
+        assert!(matches!(
+            open(&record_path, &store, None, true),
+            Err(ServerError::ShareNotFound)
+        ));
+
+        let (store, record_path) = store_with(&share("report.pdf"));
+        assert!(matches!(
+            open_share(&record_path, store.path(), None, |_, _, _| Err(
+                ServerError::ShareNotFound
+            )),
+            Err(ServerError::ShareNotFound)
+        ));
+        assert_eq!(downloads(&record_path), 0);
+    }
+
+    async fn parse_form(body: &'static str) -> Option<ShareForm> {
+        let request = axum::extract::Request::builder()
+            .method(Method::POST)
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        Form::<ShareForm>::from_request(request, &())
+            .await
+            .ok()
+            .map(|Form(form)| form)
+    }
+
+    #[tokio::test]
+    async fn parses_forms_with_blank_fields() {
+        let form = parse_form("expires_in=3600&password=&max_downloads=")
+            .await
+            .unwrap();
+        assert_eq!(form.expires_in, Some(3600));
+        assert_eq!(form.password.as_deref(), Some(""));
+        assert_eq!(form.max_downloads, None);
+
+        let form: ShareForm = serde_json::from_str(r#"{"max_downloads": 3}"#).unwrap();
+        assert_eq!(form.expires_in, None);
+        assert_eq!(form.max_downloads, Some(3));
+
+        assert!(parse_form("max_downloads=many").await.is_none());
+    }
+}
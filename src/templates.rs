@@ -1,10 +1,14 @@
 use std::cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd};
+use std::path::PathBuf;
 
 use axum::http::StatusCode;
 use sailfish::TemplateOnce;
+use serde::Serialize;
 use time::OffsetDateTime;
 
 use crate::auth::Claims;
+use crate::conflict::ConflictPolicy;
+use crate::role::Capability;
 
 #[derive(Default, TemplateOnce)]
 #[template(path = "error.stpl")]
@@ -44,6 +48,9 @@ pub struct Redirect<'a> {
 
     /// An optional message to display to the user.
     pub message: &'a str,
+
+    /// Per-item outcomes listed below the message, e.g. the files of an upload.
+    pub results: Vec<RedirectResult>,
 }
 impl Default for Redirect<'_> {
     fn default() -> Self {
@@ -54,10 +61,19 @@ impl Default for Redirect<'_> {
             success: true,
             icon: None,
             message: "",
+            results: Vec::new(),
         }
     }
 }
 
+pub struct RedirectResult {
+    /// The name of the item.
+    pub name: String,
+
+    /// Why the item failed, `None` on success.
+    pub error: Option<String>,
+}
+
 #[derive(TemplateOnce)]
 #[template(path = "home.stpl")]
 pub struct Home {
@@ -80,11 +96,20 @@ pub struct Files<'a> {
     /// The matched path.
     pub path: &'a str,
 
+    /// The directory on disk, which the user's capabilities are checked on.
+    pub full_path: PathBuf,
+
     /// The entries in the current directory.
     pub entries: Vec<FilesEntry>,
 
     /// The URL to upload files to.
     pub upload_uri: String,
+
+    /// The conflict policy preselected in the upload form.
+    pub upload_conflict: ConflictPolicy,
+
+    /// The URL of the list of uploads in progress.
+    pub uploads_uri: &'static str,
 }
 
 // Sailfish doesn't support pattern matching in the template, so this is a
@@ -102,11 +127,20 @@ pub struct FilesEntry {
 
     /// File size in bytes. For directories, it's always 0.
     pub size: u64,
+
+    /// Completed percentage when the entry is a resumable upload in progress
+    /// rather than a stored file.
+    pub upload_progress: Option<u8>,
 }
 impl Ord for FilesEntry {
     fn cmp(&self, other: &Self) -> Ordering {
         match (self.is_dir, other.is_dir) {
-            (true, true) | (false, false) => self.name.cmp(&other.name),
+            // An upload that replaces a stored file sorts right after it.
+            (true, true) | (false, false) => self.name.cmp(&other.name).then_with(|| {
+                self.upload_progress
+                    .is_some()
+                    .cmp(&other.upload_progress.is_some())
+            }),
             (true, false) => Ordering::Less,
             (false, true) => Ordering::Greater,
         }
@@ -118,6 +152,47 @@ impl PartialOrd for FilesEntry {
     }
 }
 
+#[derive(TemplateOnce)]
+#[template(path = "uploads.stpl")]
+pub struct Uploads {
+    /// User claims.
+    pub claims: Claims,
+
+    /// The route path prefix of the file explorer.
+    pub files_path_prefix: &'static str,
+
+    /// The resumable uploads in progress, sorted by target path.
+    pub entries: Vec<UploadsEntry>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadsEntry {
+    /// Path of the file being uploaded, relative to the store root.
+    pub path: String,
+
+    /// Declared size of the file in bytes.
+    pub file_size: u64,
+
+    /// Size of every chunk but the last in bytes.
+    pub chunk_size: usize,
+
+    pub completed_chunks: usize,
+
+    pub ongoing_chunks: usize,
+
+    pub not_started_chunks: usize,
+
+    /// When a chunk was last written or changed its status.
+    #[serde(with = "time::serde::rfc3339")]
+    pub last_activity: OffsetDateTime,
+}
+impl UploadsEntry {
+    pub fn total_chunks(&self) -> usize {
+        self.completed_chunks + self.ongoing_chunks + self.not_started_chunks
+    }
+}
+
 #[derive(TemplateOnce)]
 #[template(path = "login.stpl")]
 pub struct Login<'a> {
@@ -127,3 +202,16 @@ pub struct Login<'a> {
     /// The redirect URL after login.
     pub redirect: Option<&'a str>,
 }
+
+#[derive(TemplateOnce)]
+#[template(path = "share.stpl")]
+pub struct Share<'a> {
+    /// The URL of the share link, which the password is posted to.
+    pub url: &'a str,
+
+    /// The name of the shared file or directory.
+    pub name: &'a str,
+
+    /// Whether the password submitted before was wrong.
+    pub wrong_password: bool,
+}
@@ -0,0 +1,73 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json};
+use jsonwebtoken::{Header, encode};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::auth::{Claims, KEYS, verify_credentials};
+use crate::errors::ServerError;
+
+pub const ROUTE_PATH: &str = "/api/token";
+
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    username: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(with = "time::serde::rfc3339")]
+    expires_at: OffsetDateTime,
+    token_type: &'static str,
+}
+impl TokenResponse {
+    fn bearer(access_token: String, claims: &Claims) -> Self {
+        Self {
+            access_token,
+            expires_at: OffsetDateTime::from_unix_timestamp(claims.exp as i64)
+                .unwrap_or(OffsetDateTime::UNIX_EPOCH),
+            token_type: "Bearer",
+        }
+    }
+}
+
+/// Issue an access token for native clients, which send it back as
+/// `Authorization: Bearer` rather than relying on the login cookie.
+pub async fn post(Json(request): Json<TokenRequest>) -> Result<impl IntoResponse, ServerError> {
+    if !verify_credentials(&request.username, &request.password).await? {
+        return Err(ServerError::InvalidCredentials);
+    }
+
+    let claims = Claims::new(request.username);
+    let token = encode(&Header::default(), &claims, &KEYS.encoding)
+        .map_err(|error| ServerError::InternalError(error.to_string()))?;
+
+    Ok((StatusCode::OK, Json(TokenResponse::bearer(token, &claims))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::role::Role;
+    use serde_json::json;
+
+    #[test]
+    fn token_response_reports_expiry_as_rfc3339() {
+        let claims = Claims {
+            sub: "admin".to_string(),
+            exp: 1_700_000_000,
+            role: Role::Admin,
+        };
+
+        assert_eq!(
+            serde_json::to_value(TokenResponse::bearer("abc".to_string(), &claims)).unwrap(),
+            json!({
+                "access_token": "abc",
+                "expires_at": "2023-11-14T22:13:20Z",
+                "token_type": "Bearer",
+            })
+        );
+    }
+}
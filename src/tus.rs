@@ -0,0 +1,622 @@
+//! The [tus](https://tus.io/protocols/resumable-upload) 1.0 resumable upload
+//! protocol, with its creation, termination, checksum and expiration
+//! extensions. tus uploads are staged like the ones of `/upload`, the upload
+//! ID being the name of the staging directory, but are appended to in order
+//! instead of being uploaded chunk by chunk.
+
+use std::collections::HashMap;
+use std::fs::File as StdFile;
+use std::path::{Path as StdPath, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::extract::{Path, Request};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use futures::StreamExt;
+use sha1::Sha1;
+use sha2::Sha256;
+use sha2::digest::DynDigest;
+use tokio::fs;
+use tokio::task::spawn_blocking;
+
+use crate::auth::Claims;
+use crate::config::CONFIG;
+use crate::errors::ServerError;
+use crate::files;
+use crate::role::Capability;
+use crate::safe_path::safe_join;
+use crate::upload::{
+    self, OngoingChunkGuard, ResumableUploadedFileMeta, UploadPermits, file_seek_write_all,
+    staging_root,
+};
+
+pub const ROUTE_PATH_ROOT: &str = "/tus";
+pub const ROUTE_PATH: &str = "/tus/{id}";
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,termination,checksum,expiration";
+const TUS_CHECKSUM_ALGORITHMS: &str = "sha1,sha256";
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+/// Require `Tus-Resumable: 1.0.0` on every request but `OPTIONS`, and send it
+/// back on every response.
+pub async fn tus_resumable(request: Request, next: Next) -> Response {
+    let mut response = if request.method() != Method::OPTIONS
+        && request
+            .headers()
+            .get("tus-resumable")
+            .is_none_or(|version| version != TUS_VERSION)
+    {
+        ServerError::UnsupportedTusVersion.into_response()
+    } else {
+        next.run(request).await
+    };
+
+    response
+        .headers_mut()
+        .insert("tus-resumable", HeaderValue::from_static(TUS_VERSION));
+    response
+}
+
+/// Tell clients what this server supports. Doesn't require a login, so
+/// clients can probe before authenticating.
+pub async fn options() -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            ("tus-version", TUS_VERSION),
+            ("tus-extension", TUS_EXTENSIONS),
+            ("tus-checksum-algorithm", TUS_CHECKSUM_ALGORITHMS),
+        ],
+    )
+}
+
+/// Create a tus upload. Where the file goes is taken from the upload
+/// metadata, see [`target_from_metadata`].
+pub async fn post(claims: Claims, headers: HeaderMap) -> Result<Response, ServerError> {
+    claims.require(Capability::Write)?;
+    if headers.contains_key("upload-defer-length") {
+        return Err(ServerError::InvalidUploadLength);
+    }
+    let file_size: u64 = headers
+        .get("upload-length")
+        .ok_or(ServerError::MissingUploadLength)?
+        .to_str()
+        .map_err(|_| ServerError::InvalidUploadLength)?
+        .parse()
+        .map_err(|_| ServerError::InvalidUploadLength)?;
+
+    let metadata = match headers.get("upload-metadata") {
+        Some(value) => parse_metadata(
+            value
+                .to_str()
+                .map_err(|_| ServerError::InvalidUploadMetadata)?,
+        )?,
+        None => HashMap::new(),
+    };
+    let file_path = safe_join(claims.root(), &target_from_metadata(&metadata)?)?;
+    claims.authorize(Capability::Write, &file_path)?;
+
+    let mut meta = ResumableUploadedFileMeta::new_appendable(CONFIG.chunk_size, file_size);
+    meta.set_conflict(files::allowed_conflict_policy(
+        &claims,
+        match metadata.get("conflict") {
+            Some(conflict) => conflict
+                .parse()
+                .map_err(|_| ServerError::InvalidUploadMetadata)?,
+            None => CONFIG.upload_conflict,
+        },
+        &file_path,
+    ));
+    meta.set_hash(match metadata.get("hash") {
+        Some(hash) => Some(
+            hash.parse()
+                .map_err(|_| ServerError::InvalidUploadMetadata)?,
+        ),
+        None => None,
+    });
+    let upload_dir = upload::create_upload(&file_path, &mut meta).await?;
+    let location = format!("{ROUTE_PATH_ROOT}/{}", upload_id(&upload_dir));
+
+    let mut response = (StatusCode::CREATED, [(header::LOCATION, location)]).into_response();
+    // Nothing will ever be appended to an empty file, so it's done already.
+    if file_size == 0 {
+        upload::finish_upload(&upload_dir, &file_path, &meta, claims.root()).await?;
+    } else {
+        insert_upload_expires(response.headers_mut(), &upload_dir);
+    }
+    Ok(response)
+}
+
+/// Report how much of a tus upload the server has.
+pub async fn head(claims: Claims, Path(id): Path<String>) -> Result<Response, ServerError> {
+    let (upload_dir, meta) = find_upload(&claims, &id).await?;
+    let offset = meta.offset().ok_or(ServerError::NotAnAppendableUpload)?;
+
+    let mut response = (
+        StatusCode::OK,
+        [
+            (header::CACHE_CONTROL, "no-store".to_string()),
+            (
+                header::HeaderName::from_static("upload-offset"),
+                offset.to_string(),
+            ),
+            (
+                header::HeaderName::from_static("upload-length"),
+                meta.file_size().to_string(),
+            ),
+        ],
+    )
+        .into_response();
+    insert_upload_expires(response.headers_mut(), &upload_dir);
+    Ok(response)
+}
+
+/// Append to a tus upload, moving the file into place once it is complete.
+pub async fn patch(
+    claims: Claims,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, ServerError> {
+    if headers
+        .get(header::CONTENT_TYPE)
+        .is_none_or(|content_type| content_type != OFFSET_OCTET_STREAM)
+    {
+        return Err(ServerError::InvalidUploadContentType);
+    }
+    let offset: u64 = headers
+        .get("upload-offset")
+        .ok_or(ServerError::MissingUploadOffset)?
+        .to_str()
+        .map_err(|_| ServerError::InvalidUploadOffset)?
+        .parse()
+        .map_err(|_| ServerError::InvalidUploadOffset)?;
+    let checksum = match headers.get("upload-checksum") {
+        Some(value) => Some(UploadChecksum::parse(
+            value
+                .to_str()
+                .map_err(|_| ServerError::InvalidUploadChecksum)?,
+        )?),
+        None => None,
+    };
+    let content_length: Option<usize> = match headers.get(header::CONTENT_LENGTH) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .map_err(|_| ServerError::InvalidContentLength)?
+                .parse()
+                .map_err(|_| ServerError::InvalidContentLength)?,
+        ),
+        None => None,
+    };
+
+    let (upload_dir, meta) = find_upload(&claims, &id).await?;
+    meta.offset().ok_or(ServerError::NotAnAppendableUpload)?;
+    let file_path = safe_join(&CONFIG.store_path, meta.target())?;
+
+    // An append holds at most a chunk's worth of bytes in flight, like a
+    // chunk of `/upload` does.
+    let _upload_permits = UploadPermits::acquire(
+        &upload_dir,
+        content_length
+            .unwrap_or(meta.chunk_size())
+            .min(meta.chunk_size()),
+    )?;
+
+    let (appending_tx, appending_rx) = std::sync::mpsc::channel();
+    ResumableUploadedFileMeta::update_meta_file(&upload_dir, move |meta| {
+        let _ = appending_tx.send(meta.begin_append(offset)?);
+        Ok(())
+    })
+    .await?;
+
+    let new_offset = if appending_rx.recv().unwrap_or(false) {
+        // See `upload::put`: no `.await` between marking the chunk `Ongoing`
+        // and constructing the guard.
+        let guard = OngoingChunkGuard::new(
+            upload_dir.clone(),
+            (offset / meta.chunk_size() as u64) as usize,
+        );
+
+        let upload_file = fs::OpenOptions::new()
+            .write(true)
+            .open(upload::data_path(&upload_dir))
+            .await?
+            .into_std()
+            .await;
+        let (written, write_result) = append_body_to_file(
+            Arc::new(upload_file),
+            offset,
+            meta.file_size() - offset,
+            checksum,
+            body,
+        )
+        .await;
+
+        // Without a checksum, whatever made it to disk counts, so an
+        // interrupted append can be resumed from where it broke off. With one,
+        // the bytes can't be trusted unless all of them arrived and matched.
+        let new_offset = match (&write_result, checksum) {
+            (Err(_), Some(_)) => offset,
+            _ => offset + written,
+        };
+        ResumableUploadedFileMeta::update_meta_file(&upload_dir, move |meta| {
+            meta.end_append(new_offset);
+            Ok(())
+        })
+        .await?;
+        guard.commit();
+
+        if let Err(err) = write_result {
+            return Err(match err {
+                ServerError::UploadChecksumMismatch | ServerError::InvalidContentLength => err,
+                _ => ServerError::UploadChunkFailed,
+            });
+        }
+        new_offset
+    } else {
+        meta.file_size()
+    };
+
+    let mut response = (
+        StatusCode::NO_CONTENT,
+        [("upload-offset", new_offset.to_string())],
+    )
+        .into_response();
+    if new_offset == meta.file_size() {
+        let meta = ResumableUploadedFileMeta::read_from_file(&upload_dir)
+            .await?
+            .ok_or(ServerError::FileIsNotCreated)?;
+        upload::finish_upload(&upload_dir, &file_path, &meta, claims.root()).await?;
+    } else {
+        insert_upload_expires(response.headers_mut(), &upload_dir);
+    }
+    Ok(response)
+}
+
+/// Terminate a tus upload, removing everything staged for it.
+pub async fn delete(claims: Claims, Path(id): Path<String>) -> Result<StatusCode, ServerError> {
+    let (upload_dir, _) = find_upload(&claims, &id).await?;
+    upload::remove_upload(upload_dir).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The staging directory and meta of the upload `id`, for a user who may
+/// upload. Uploads of files outside of their root don't exist for them.
+async fn find_upload(
+    claims: &Claims,
+    id: &str,
+) -> Result<(PathBuf, ResumableUploadedFileMeta), ServerError> {
+    claims.require(Capability::Write)?;
+    let upload_dir = find_upload_dir(id)?;
+    let meta = ResumableUploadedFileMeta::read_from_file(&upload_dir)
+        .await?
+        .ok_or(ServerError::FileIsNotCreated)?;
+    let file_path = CONFIG.store_path.join(meta.target());
+    if !file_path.starts_with(claims.root()) {
+        return Err(ServerError::FileIsNotCreated);
+    }
+    claims.authorize(Capability::Write, &file_path)?;
+    Ok((upload_dir, meta))
+}
+
+/// The staging directory of the upload `id`. IDs are staging directory names,
+/// so anything but 64 lowercase hex digits can't be one.
+fn find_upload_dir(id: &str) -> Result<PathBuf, ServerError> {
+    if !is_upload_id(id) {
+        return Err(ServerError::FileIsNotCreated);
+    }
+    Ok(staging_root(&CONFIG.store_path).join(id))
+}
+
+fn is_upload_id(id: &str) -> bool {
+    id.len() == 64
+        && id
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+}
+
+fn upload_id(upload_dir: &StdPath) -> String {
+    upload_dir
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Set `Upload-Expires` to when the sweeper will remove the upload if nothing
+/// happens to it until then. Uploads never expire with a TTL of 0.
+fn insert_upload_expires(headers: &mut HeaderMap, upload_dir: &StdPath) {
+    if CONFIG.upload_ttl == 0 {
+        return;
+    }
+    if let Some(last_activity) = upload::last_activity(upload_dir) {
+        let expires = last_activity + Duration::from_secs(CONFIG.upload_ttl);
+        if let Ok(value) = HeaderValue::from_str(&httpdate::fmt_http_date(expires)) {
+            headers.insert("upload-expires", value);
+        }
+    }
+}
+
+/// Parse `Upload-Metadata`: comma-separated pairs of a key and a base64
+/// encoded value, separated by a space. The value may be left out.
+fn parse_metadata(value: &str) -> Result<HashMap<String, String>, ServerError> {
+    let mut metadata = HashMap::new();
+
+    for pair in value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, value)) => {
+                let value = BASE64
+                    .decode(value.trim())
+                    .ok()
+                    .and_then(|value| String::from_utf8(value).ok())
+                    .ok_or(ServerError::InvalidUploadMetadata)?;
+                (key, value)
+            }
+            None => (pair, String::new()),
+        };
+        if metadata.insert(key.to_string(), value).is_some() {
+            return Err(ServerError::InvalidUploadMetadata);
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// The path of the uploaded file relative to the store root: `relativePath`,
+/// `filename` or `name`, the first one set, inside the optional `directory`.
+fn target_from_metadata(metadata: &HashMap<String, String>) -> Result<String, ServerError> {
+    let name = ["relativePath", "filename", "name"]
+        .into_iter()
+        .filter_map(|key| metadata.get(key))
+        .find(|name| !name.is_empty())
+        .ok_or(ServerError::InvalidUploadMetadata)?;
+
+    Ok(match metadata.get("directory") {
+        Some(directory) if !directory.is_empty() => {
+            format!("{}/{name}", directory.trim_end_matches('/'))
+        }
+        _ => name.clone(),
+    })
+}
+
+/// The checksum of the bytes of one `PATCH`, from `Upload-Checksum`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UploadChecksum {
+    Sha1([u8; 20]),
+    Sha256([u8; 32]),
+}
+impl UploadChecksum {
+    /// Parse `<algorithm> <base64 digest>`.
+    fn parse(value: &str) -> Result<Self, ServerError> {
+        let (algorithm, digest) = value
+            .trim()
+            .split_once(' ')
+            .ok_or(ServerError::InvalidUploadChecksum)?;
+        let digest = BASE64
+            .decode(digest.trim())
+            .map_err(|_| ServerError::InvalidUploadChecksum)?;
+        match algorithm {
+            "sha1" => Ok(Self::Sha1(
+                digest
+                    .try_into()
+                    .map_err(|_| ServerError::InvalidUploadChecksum)?,
+            )),
+            "sha256" => Ok(Self::Sha256(
+                digest
+                    .try_into()
+                    .map_err(|_| ServerError::InvalidUploadChecksum)?,
+            )),
+            _ => Err(ServerError::UnsupportedChecksumAlgorithm),
+        }
+    }
+
+    fn hasher(&self) -> Box<dyn DynDigest + Send> {
+        match self {
+            Self::Sha1(_) => Box::new(Sha1::default()),
+            Self::Sha256(_) => Box::new(Sha256::default()),
+        }
+    }
+
+    fn digest(&self) -> &[u8] {
+        match self {
+            Self::Sha1(digest) => digest,
+            Self::Sha256(digest) => digest,
+        }
+    }
+}
+
+/// Write `body` to `file` from `offset` on, failing if it's longer than
+/// `max_len` or, when given, doesn't match `checksum`. Returns how many bytes
+/// were written along with the outcome, since tus keeps the bytes of an
+/// interrupted append.
+async fn append_body_to_file(
+    file: Arc<StdFile>,
+    offset: u64,
+    max_len: u64,
+    checksum: Option<UploadChecksum>,
+    body: Body,
+) -> (u64, Result<(), ServerError>) {
+    let mut body = body.into_data_stream();
+    let mut total_written = 0u64;
+    let mut hasher = checksum.map(|checksum| checksum.hasher());
+
+    while let Some(chunk) = body.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                return (
+                    total_written,
+                    Err(ServerError::Custom {
+                        status: StatusCode::BAD_REQUEST,
+                        message: err.to_string(),
+                    }),
+                );
+            }
+        };
+
+        let chunk_len = chunk.len() as u64;
+        if total_written + chunk_len > max_len {
+            return (total_written, Err(ServerError::InvalidContentLength));
+        }
+
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&chunk);
+        }
+
+        let file = file.clone();
+        let chunk_offset = offset + total_written;
+        match spawn_blocking(move || file_seek_write_all(&file, chunk_offset, &chunk)).await {
+            Ok(Ok(())) => total_written += chunk_len,
+            Ok(Err(err)) => return (total_written, Err(err)),
+            Err(err) => return (total_written, Err(err.into())),
+        }
+    }
+
+    if let (Some(hasher), Some(checksum)) = (hasher, checksum)
+        && *hasher.finalize() != *checksum.digest()
+    {
+        return (total_written, Err(ServerError::UploadChecksumMismatch));
+    }
+
+    (total_written, Ok(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_metadata() {
+        let metadata =
+            parse_metadata("filename d29ybGRfZG9taW5hdGlvbl9wbGFuLnBkZg==, is_confidential")
+                .unwrap();
+        assert_eq!(metadata["filename"], "world_domination_plan.pdf");
+        assert_eq!(metadata["is_confidential"], "");
+        assert_eq!(metadata.len(), 2);
+
+        assert!(parse_metadata("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_metadata() {
+        for value in [
+            "filename not-base64!",
+            "filename /w==",
+            "filename YQ==,filename Yg==",
+        ] {
+            assert!(parse_metadata(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn target_prefers_relative_path_inside_directory() {
+        let metadata = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+
+        assert_eq!(
+            target_from_metadata(&metadata(&[("filename", "a.txt")])).unwrap(),
+            "a.txt"
+        );
+        assert_eq!(
+            target_from_metadata(&metadata(&[("name", "a.txt"), ("relativePath", "")])).unwrap(),
+            "a.txt"
+        );
+        assert_eq!(
+            target_from_metadata(&metadata(&[
+                ("filename", "a.txt"),
+                ("relativePath", "photos/a.txt"),
+                ("directory", "backup/"),
+            ]))
+            .unwrap(),
+            "backup/photos/a.txt"
+        );
+        assert!(target_from_metadata(&metadata(&[("directory", "backup")])).is_err());
+    }
+
+    #[test]
+    fn parses_checksums() {
+        assert_eq!(
+            UploadChecksum::parse("sha1 gf6L/odXbD7LIkJvjleEc4KRes8=").unwrap(),
+            UploadChecksum::Sha1([
+                0x81, 0xfe, 0x8b, 0xfe, 0x87, 0x57, 0x6c, 0x3e, 0xcb, 0x22, 0x42, 0x6f, 0x8e, 0x57,
+                0x84, 0x73, 0x82, 0x91, 0x7a, 0xcf,
+            ])
+        );
+        assert!(matches!(
+            UploadChecksum::parse("sha256 iNQmb9TmM40TuEX88olXnSCciXgjuSF9o+Fhk28DFYk="),
+            Ok(UploadChecksum::Sha256(_))
+        ));
+        assert!(matches!(
+            UploadChecksum::parse("md5 4vxxTEcn7pOV8yTNLn8zHw=="),
+            Err(ServerError::UnsupportedChecksumAlgorithm)
+        ));
+        for value in [
+            "sha1",
+            "sha1 not-base64!",
+            "sha256 gf6L/odXbD7LIkJvjleEc4KRes8=",
+        ] {
+            assert!(
+                matches!(
+                    UploadChecksum::parse(value),
+                    Err(ServerError::InvalidUploadChecksum)
+                ),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn only_staging_directory_names_are_upload_ids() {
+        assert!(is_upload_id(&"0123456789abcdef".repeat(4)));
+        for id in [
+            "",
+            "..",
+            "0123456789abcdef",
+            &"0123456789ABCDEF".repeat(4),
+            &format!("{}/a", "0".repeat(62)),
+        ] {
+            assert!(!is_upload_id(id), "{id}");
+        }
+    }
+
+    #[tokio::test]
+    async fn appends_and_verifies_checksum() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("data");
+        std::fs::write(&path, [0u8; 8]).unwrap();
+        let file = Arc::new(StdFile::options().write(true).open(&path).unwrap());
+
+        let checksum =
+            UploadChecksum::parse("sha256 iNQmb9TmM40TuEX88olXnSCciXgjuSF9o+Fhk28DFYk=").unwrap();
+        let (written, result) =
+            append_body_to_file(file.clone(), 2, 6, Some(checksum), Body::from("abcd")).await;
+        assert_eq!(written, 4);
+        assert!(result.is_ok());
+        assert_eq!(std::fs::read(&path).unwrap(), b"\0\0abcd\0\0");
+
+        let (written, result) =
+            append_body_to_file(file.clone(), 2, 6, Some(checksum), Body::from("abce")).await;
+        assert_eq!(written, 4);
+        assert!(matches!(result, Err(ServerError::UploadChecksumMismatch)));
+
+        let (written, result) = append_body_to_file(file, 6, 2, None, Body::from("xyz")).await;
+        assert_eq!(written, 0);
+        assert!(matches!(result, Err(ServerError::InvalidContentLength)));
+    }
+}
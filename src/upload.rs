@@ -4,28 +4,47 @@ use std::io::BufReader;
 use std::io::{Seek, SeekFrom, Write};
 use std::path::{Path as StdPath, PathBuf};
 use std::sync::{Arc, LazyLock, Mutex, Weak};
+use std::time::{Duration, SystemTime};
 
 use axum::body::Body;
 use axum::extract::Path;
 use axum::http::{StatusCode, header::HeaderMap};
 use axum::response::{IntoResponse, Json};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use futures::StreamExt;
 use rustix::fd::AsFd;
 use rustix::fs::{FallocateFlags, FlockOperation, fallocate, flock};
 use rustix::io::pwrite;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
+use sha2::{Digest, Sha256};
 use tokio::fs;
 use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio::task::spawn_blocking;
 
 use crate::auth::Claims;
+use crate::chunk_map::ChunkMap;
 use crate::config::CONFIG;
+use crate::conflict::{self, ConflictPolicy};
 use crate::errors::ServerError;
-use crate::safe_path::safe_join;
+use crate::file_hash::{FileHash, parse_hex_digest};
+use crate::files;
+use crate::role::Capability;
+use crate::safe_path::{STATE_DIR_NAME, safe_join};
+use crate::share;
 
 pub const ROUTE_PATH: &str = "/upload/{*file_path}";
+const META_FILE_NAME: &str = "meta.json";
+const DATA_FILE_NAME: &str = "data";
+const CHUNK_MAP_FILE_NAME: &str = "chunks";
+/// Created in [`STATE_DIR_NAME`] once the uploads of older versions were
+/// migrated, see [`migrate_legacy_uploads`].
+const LEGACY_UPLOADS_MIGRATED_FILE_NAME: &str = "legacy-uploads-migrated";
 const UPLOAD_BYTE_BUDGET_UNIT: usize = 1024 * 1024;
+/// Upper bound on how long an expired upload or share link waits for the
+/// background sweeper.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 static ACTIVE_UPLOAD_CHUNKS: LazyLock<Arc<Semaphore>> =
     LazyLock::new(|| Arc::new(Semaphore::new(CONFIG.max_active_upload_chunks)));
@@ -71,47 +90,161 @@ impl ChunkStatus {
 pub struct ResumableUploadedFileMeta {
     chunk_size: usize,
     file_size: u64,
-    chunks: HashMap<usize, ChunkStatus>,
+    /// Stored in its own file next to the meta, see [`ChunkMap`].
+    #[serde(skip)]
+    chunks: ChunkMap,
+    /// The chunk statuses of metas written before the chunk map file existed.
+    /// Only read, to migrate them to a chunk map on their next update.
+    #[serde(default, rename = "chunks", skip_serializing)]
+    legacy_chunks: Option<HashMap<usize, ChunkStatus>>,
+    /// Applied when the finished upload is moved into place. Metas written
+    /// before conflict policies existed always overwrote, hence the default.
+    #[serde(default = "legacy_conflict_policy")]
+    conflict: ConflictPolicy,
+    /// Path of the file being uploaded, relative to the store root.
+    #[serde(default)]
+    target: String,
+    /// What the assembled file has to hash to before it is moved into place.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash: Option<FileHash>,
+    /// Bytes received so far by an upload created through tus, which appends
+    /// to the file instead of uploading chunks in any order. Its chunks are
+    /// only kept for bookkeeping: the ones below the offset are completed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    offset: Option<u64>,
 }
 
 impl ResumableUploadedFileMeta {
     /// Create a new instance of `ResumableUploadedFileMeta`.
     pub fn new(chunk_size: usize, file_size: u64) -> Self {
-        let chunks_count = (file_size as f64 / chunk_size as f64).ceil() as usize;
         Self {
             chunk_size,
             file_size,
-            chunks: (0..chunks_count)
-                .map(|i| (i, ChunkStatus::NotStarted))
+            chunks: ChunkMap::new(file_size.div_ceil(chunk_size as u64) as usize),
+            legacy_chunks: None,
+            conflict: ConflictPolicy::default(),
+            target: String::new(),
+            hash: None,
+            offset: None,
+        }
+    }
+
+    /// Create the meta of an upload that is appended to, see `offset`.
+    pub fn new_appendable(chunk_size: usize, file_size: u64) -> Self {
+        Self {
+            offset: Some(0),
+            ..Self::new(chunk_size, file_size)
+        }
+    }
+
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    pub fn set_conflict(&mut self, conflict: ConflictPolicy) {
+        self.conflict = conflict;
+    }
+
+    pub fn set_hash(&mut self, hash: Option<FileHash>) {
+        self.hash = hash;
+    }
+
+    /// Claim an appendable upload for appending at `offset`, by marking the
+    /// chunk at `offset / chunk_size` `Ongoing`. Fails when another append is
+    /// in flight or `offset` isn't where the upload left off. Returns `false`
+    /// without marking anything when the upload already holds all its bytes.
+    pub fn begin_append(&mut self, offset: u64) -> Result<bool, ServerError> {
+        let current = self.offset.ok_or(ServerError::NotAnAppendableUpload)?;
+        if self.chunks.iter().any(|status| status.is_ongoing()) {
+            return Err(ServerError::UploadIsBusy);
+        }
+        if offset != current {
+            return Err(ServerError::UploadOffsetMismatch);
+        }
+        if current == self.file_size {
+            return Ok(false);
+        }
+
+        let chunk_index = (current / self.chunk_size as u64) as usize;
+        self.chunks.set(chunk_index, ChunkStatus::Ongoing);
+        Ok(true)
+    }
+
+    /// Record that an appendable upload now holds `offset` bytes, completing
+    /// the chunks below it. Also ends the append started by `begin_append`.
+    pub fn end_append(&mut self, offset: u64) {
+        self.offset = Some(offset);
+        for chunk_index in 0..self.chunks.len() {
+            let chunk_end = ((chunk_index + 1) as u64 * self.chunk_size as u64).min(self.file_size);
+            let status = if chunk_end <= offset {
+                ChunkStatus::Completed
+            } else {
+                ChunkStatus::NotStarted
+            };
+            self.chunks.set(chunk_index, status);
+        }
+    }
+
+    /// Percentage of the chunks that are completed, rounded down.
+    pub fn progress(&self) -> u8 {
+        if self.chunks.is_empty() {
+            return 0;
+        }
+        let completed = self
+            .chunks
+            .iter()
+            .filter(|status| status.is_completed())
+            .count();
+        (completed * 100 / self.chunks.len()) as u8
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    pub fn chunk_statuses(&self) -> impl Iterator<Item = ChunkStatus> + '_ {
+        self.chunks.iter()
+    }
+
+    /// What clients are told about the upload: the meta, plus which chunks
+    /// they still have to upload.
+    pub fn status(&self) -> ResumableUploadStatus<'_> {
+        ResumableUploadStatus {
+            meta: self,
+            total_chunks: self.chunks.len(),
+            missing_chunks: self
+                .chunks
+                .missing_ranges()
+                .into_iter()
+                .map(|range| [range.start, range.end])
                 .collect(),
         }
     }
 
-    /// Return the meta file path for the given file path.
-    pub fn path<T: AsRef<std::path::Path>>(file_path: T) -> PathBuf {
-        let file_name = format!(
-            "{}.resumable-meta",
-            // The first `unwrap()` is safe because `file_path` is guaranteed to have a file name,
-            // and for the second `unwrap()`, since we are unlikely to support OSes that allow
-            // non-UTF-8 file names, it's acceptable to panic if the file name is not valid UTF-8.
-            file_path.as_ref().file_name().unwrap().to_str().unwrap()
-        );
-        file_path.as_ref().with_file_name(file_name)
+    pub fn target(&self) -> &str {
+        &self.target
     }
 
-    /// Check if the meta file exists for the given file path.
-    pub fn exists<T: AsRef<std::path::Path>>(file_path: T) -> Result<bool, std::io::Error> {
-        Self::path(file_path).try_exists()
+    /// Return the meta file path for the given upload directory.
+    pub fn path<T: AsRef<std::path::Path>>(upload_dir: T) -> PathBuf {
+        upload_dir.as_ref().join(META_FILE_NAME)
     }
 
-    /// Read the meta information from the given file path.
+    /// Read the meta information from the given upload directory.
     pub async fn read_from_file<T: AsRef<std::path::Path>>(
-        file_path: T,
+        upload_dir: T,
     ) -> Result<Option<Self>, ServerError> {
-        match fs::File::open(Self::path(file_path.as_ref())).await {
+        let upload_dir = upload_dir.as_ref().to_path_buf();
+        match fs::File::open(Self::path(&upload_dir)).await {
             Ok(tokio_file) => {
                 let std_file = tokio_file.into_std().await;
-                let meta = spawn_blocking(move || Self::read_from_file_sync(&std_file)).await??;
+                let meta =
+                    spawn_blocking(move || Self::read_from_file_sync(&upload_dir, &std_file))
+                        .await??;
                 Ok(Some(meta))
             }
 
@@ -121,48 +254,110 @@ impl ResumableUploadedFileMeta {
         }
     }
 
-    fn read_from_file_sync(meta_file: &StdFile) -> Result<Self, ServerError> {
+    /// Read the meta from `meta_file`, and its chunk map from `upload_dir`.
+    /// A meta written before chunk maps existed gets one built from its
+    /// legacy chunk statuses, unless the chunk map file is already there.
+    fn read_from_file_sync(upload_dir: &StdPath, meta_file: &StdFile) -> Result<Self, ServerError> {
         let reader = BufReader::new(meta_file);
-        let meta: ResumableUploadedFileMeta =
+        let mut meta: ResumableUploadedFileMeta =
             serde_json::from_reader(reader).map_err(|_| ServerError::UploadMetaIsBroken)?;
+        if meta.chunk_size == 0 {
+            return Err(ServerError::UploadMetaIsBroken);
+        }
+        let chunk_count = meta.file_size.div_ceil(meta.chunk_size as u64) as usize;
+
+        meta.chunks = match std::fs::read(chunk_map_path(upload_dir)) {
+            Ok(bytes) => ChunkMap::from_bytes(chunk_count, bytes),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                meta.legacy_chunks.as_ref().and_then(|legacy_chunks| {
+                    let mut chunks = ChunkMap::new(chunk_count);
+                    for (&chunk_index, &status) in legacy_chunks {
+                        if chunk_index >= chunk_count {
+                            return None;
+                        }
+                        chunks.set(chunk_index, status);
+                    }
+                    Some(chunks)
+                })
+            }
+            Err(err) => return Err(err.into()),
+        }
+        .ok_or(ServerError::UploadMetaIsBroken)?;
 
         Ok::<_, ServerError>(meta)
     }
 
-    /// Atomically read, mutate, and write the `.resumable-meta` file under an
-    /// exclusive file lock. The updater may veto the write by returning an
-    /// error; in that case the meta file is left untouched and the error is
-    /// propagated to the caller. This is the only safe place to do
+    /// Write the chunk map of a new upload, before its meta.
+    async fn write_chunk_map(&self, upload_dir: &StdPath) -> Result<(), ServerError> {
+        fs::write(chunk_map_path(upload_dir), self.chunks.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Atomically read, mutate, and write the meta and chunk map files under
+    /// an exclusive lock of the meta file. The updater may veto the write by
+    /// returning an error; in that case both files are left untouched and the
+    /// error is propagated to the caller. This is the only safe place to do
     /// check-and-set on chunk status.
     ///
-    /// If the updater leaves the meta byte-identical to what was on disk, no
-    /// write is performed — `set_len(0)` is only called when we actually have
-    /// new bytes to write, so the no-op path is free and safe.
-    pub async fn update_meta_file<F, U>(file_path: F, updater: U) -> Result<(), ServerError>
+    /// Chunk status changes are written in place, only the bytes of the chunk
+    /// map that changed. The meta file is only rewritten when the updater
+    /// changed one of its fields — `set_len(0)` is only called when we
+    /// actually have new bytes to write, so the no-op path is free and safe.
+    /// A meta with legacy chunk statuses is migrated: its chunk map file is
+    /// written in full, then the meta is rewritten without them.
+    pub async fn update_meta_file<F, U>(upload_dir: F, updater: U) -> Result<(), ServerError>
     where
         F: AsRef<std::path::Path>,
         U: FnOnce(&mut Self) -> Result<(), ServerError> + Send + 'static,
     {
-        let meta_file_path = Self::path(file_path.as_ref());
+        let upload_dir = upload_dir.as_ref().to_path_buf();
+        let meta_file_path = Self::path(&upload_dir);
 
         spawn_blocking(move || {
             let mut meta_file = StdOpenOptions::new()
                 .read(true)
                 .write(true)
                 .open(&meta_file_path)
-                .map_err(|err| ServerError::Custom {
-                    status: StatusCode::INTERNAL_SERVER_ERROR,
-                    message: err.to_string(),
+                .map_err(|err| match err.kind() {
+                    std::io::ErrorKind::NotFound => ServerError::FileIsNotCreated,
+                    _ => ServerError::Custom {
+                        status: StatusCode::INTERNAL_SERVER_ERROR,
+                        message: err.to_string(),
+                    },
                 })?;
 
             flock(meta_file.as_fd(), FlockOperation::LockExclusive)?;
+            // Removed while waiting for the lock, e.g. by the sweeper.
+            if !meta_file_path.exists() {
+                return Err(ServerError::FileIsNotCreated);
+            }
 
-            let mut meta = Self::read_from_file_sync(&meta_file)?;
+            let mut meta = Self::read_from_file_sync(&upload_dir, &meta_file)?;
             let before = serde_json::to_vec(&meta).unwrap();
+            let chunks_before = meta.chunks.clone();
             updater(&mut meta)?;
             let after = serde_json::to_vec(&meta).unwrap();
 
-            if before != after {
+            let chunk_map_path = chunk_map_path(&upload_dir);
+            let migrate = meta.legacy_chunks.take().is_some();
+            if migrate {
+                // Renamed into place, so a crash never leaves a partial chunk
+                // map behind that would be preferred over the legacy statuses.
+                let new_chunk_map_path = chunk_map_path.with_extension("new");
+                std::fs::write(&new_chunk_map_path, meta.chunks.as_bytes())?;
+                std::fs::rename(&new_chunk_map_path, &chunk_map_path)?;
+            } else if meta.chunks != chunks_before {
+                let chunk_map_file = StdOpenOptions::new().write(true).open(&chunk_map_path)?;
+                for offset in meta.chunks.changed_bytes(&chunks_before) {
+                    file_seek_write_all(
+                        &chunk_map_file,
+                        offset as u64,
+                        &meta.chunks.as_bytes()[offset..=offset],
+                    )?;
+                }
+            }
+
+            if migrate || before != after {
                 meta_file.set_len(0)?;
                 meta_file.seek(SeekFrom::Start(0))?;
                 meta_file.write_all(&after)?;
@@ -176,15 +371,223 @@ impl ResumableUploadedFileMeta {
     }
 }
 
+/// The meta of an upload as returned by `GET` and `POST /upload/{path}`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResumableUploadStatus<'a> {
+    #[serde(flatten)]
+    meta: &'a ResumableUploadedFileMeta,
+    total_chunks: usize,
+    /// The chunks that aren't completed yet, as `[start, end)` index ranges.
+    missing_chunks: Vec<[usize; 2]>,
+}
+
+/// Directory every resumable upload is staged in until it completes.
+pub fn staging_root(store_path: &StdPath) -> PathBuf {
+    store_path.join(STATE_DIR_NAME).join("uploads")
+}
+
+/// The staging directory of the upload of `target`, a path relative to the
+/// store root. Uploads are keyed by a hash of their target so any path maps
+/// to a flat, fixed-length directory name.
+pub fn upload_dir(store_path: &StdPath, target: &str) -> PathBuf {
+    staging_root(store_path).join(format!("{:x}", Sha256::digest(target.as_bytes())))
+}
+
+/// The path of `file_path` relative to the store root, `/`-separated, as
+/// recorded in the meta and hashed into the upload ID.
+fn relative_target(file_path: &StdPath) -> String {
+    relative_path(file_path, &CONFIG.store_path)
+}
+
+/// The path of `file_path` relative to `base`, `/`-separated.
+fn relative_path(file_path: &StdPath, base: &StdPath) -> String {
+    file_path
+        .strip_prefix(base)
+        .unwrap_or(file_path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Directory multipart and `PUT` uploads are written to until they are
+/// complete, so they never show up next to their target half-written.
+pub fn form_upload_root(store_path: &StdPath) -> PathBuf {
+    store_path.join(STATE_DIR_NAME).join("form-uploads")
+}
+
+/// Remove the form uploads a previous run was writing when it died. Only safe
+/// before the server accepts requests.
+pub async fn clear_form_uploads(store_path: &StdPath) {
+    let form_upload_root = form_upload_root(store_path);
+    match fs::remove_dir_all(&form_upload_root).await {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => eprintln!(
+            "failed to remove leftover uploads in {}: {err}",
+            form_upload_root.display()
+        ),
+    }
+}
+
+/// Move the uploads older versions kept next to their target, a
+/// `.resumable-meta` and a `.resumable-upload` file, into the staging root,
+/// where they are continued, listed, recovered and expire like any other
+/// upload. Their metas are migrated like any other legacy meta on their first
+/// update. The whole store is only walked until a walk succeeds, later starts
+/// skip it. Run at startup, before the staged uploads are recovered.
+pub async fn migrate_legacy_uploads(store_path: &StdPath) {
+    let store_path = store_path.to_path_buf();
+    if let Err(err) = spawn_blocking(move || migrate_legacy_uploads_sync(&store_path)).await {
+        eprintln!("upload migration: failed to walk the store: {err}");
+    }
+}
+
+fn migrate_legacy_uploads_sync(store_path: &StdPath) {
+    let marker = store_path
+        .join(STATE_DIR_NAME)
+        .join(LEGACY_UPLOADS_MIGRATED_FILE_NAME);
+    if marker.exists() {
+        return;
+    }
+
+    let mut failed = false;
+    let mut stack = vec![store_path.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                if entry.file_name() != STATE_DIR_NAME {
+                    stack.push(path);
+                }
+            } else if file_type.is_file()
+                && path.extension().is_some_and(|ext| ext == "resumable-meta")
+                && is_upload_artifact_sync(&path)
+                && let Err(err) = migrate_legacy_upload(store_path, &path)
+            {
+                eprintln!(
+                    "upload migration: failed to migrate {}: {err}",
+                    path.display()
+                );
+                failed = true;
+            }
+        }
+    }
+
+    if !failed
+        && let Err(err) = std::fs::create_dir_all(store_path.join(STATE_DIR_NAME))
+            .and_then(|()| std::fs::write(&marker, b""))
+    {
+        eprintln!(
+            "upload migration: failed to create {}: {err}",
+            marker.display()
+        );
+    }
+}
+
+/// Stage the upload whose legacy meta is at `meta_path`, keeping the time of
+/// its last activity so it expires when it would have.
+fn migrate_legacy_upload(store_path: &StdPath, meta_path: &StdPath) -> std::io::Result<()> {
+    let data_file_path = meta_path.with_extension("resumable-upload");
+    let target = relative_path(&meta_path.with_extension(""), store_path);
+    let upload_dir = upload_dir(store_path, &target);
+
+    if upload_dir.exists() {
+        // The target was uploaded again after upgrading, which supersedes it.
+        std::fs::remove_file(&data_file_path)?;
+        std::fs::remove_file(meta_path)?;
+        eprintln!("upload migration: removed superseded upload of `{target}`");
+        return Ok(());
+    }
+
+    let legacy_meta = std::fs::read(meta_path)?;
+    let modified = std::fs::metadata(meta_path)?.modified()?;
+    // A broken meta is staged as it is, to expire like other broken uploads.
+    let meta = match serde_json::from_slice::<serde_json::Map<_, _>>(&legacy_meta) {
+        Ok(mut meta) => {
+            meta.insert("target".to_string(), target.clone().into());
+            serde_json::to_vec(&meta).map_err(std::io::Error::other)?
+        }
+        Err(_) => legacy_meta,
+    };
+
+    std::fs::create_dir_all(&upload_dir)?;
+    let mut meta_file = StdFile::create(ResumableUploadedFileMeta::path(&upload_dir))?;
+    meta_file.write_all(&meta)?;
+    meta_file.set_modified(modified)?;
+    std::fs::rename(&data_file_path, data_path(&upload_dir))?;
+    StdFile::open(&upload_dir)?.set_modified(modified)?;
+    std::fs::remove_file(meta_path)?;
+
+    eprintln!(
+        "upload migration: staged upload of `{target}` in {}",
+        upload_dir.display()
+    );
+    Ok(())
+}
+
+/// Directory finished uploads that don't match their expected hash are moved
+/// to, instead of to their target.
+pub fn quarantine_root(store_path: &StdPath) -> PathBuf {
+    store_path.join(STATE_DIR_NAME).join("quarantine")
+}
+
+/// Move the finished upload in `upload_dir` into `quarantine_root`, replacing
+/// an earlier quarantined upload of the same target, and return where it went.
+async fn quarantine(
+    upload_dir: &StdPath,
+    quarantine_root: &StdPath,
+) -> Result<PathBuf, ServerError> {
+    let quarantined = quarantine_root.join(upload_dir.file_name().unwrap_or_default());
+    fs::create_dir_all(quarantine_root).await?;
+    match fs::remove_dir_all(&quarantined).await {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    fs::rename(upload_dir, &quarantined).await?;
+    Ok(quarantined)
+}
+
+pub fn data_path(upload_dir: &StdPath) -> PathBuf {
+    upload_dir.join(DATA_FILE_NAME)
+}
+
+fn chunk_map_path(upload_dir: &StdPath) -> PathBuf {
+    upload_dir.join(CHUNK_MAP_FILE_NAME)
+}
+
+/// Read every staged upload, skipping directories without a readable meta.
+/// Cost is proportional to the number of uploads in progress, not to the
+/// size of the store.
+pub fn list_uploads_sync(staging_root: &StdPath) -> Vec<(PathBuf, ResumableUploadedFileMeta)> {
+    find_upload_dirs(staging_root)
+        .into_iter()
+        .filter_map(|upload_dir| {
+            let meta_file = StdFile::open(ResumableUploadedFileMeta::path(&upload_dir)).ok()?;
+            let meta =
+                ResumableUploadedFileMeta::read_from_file_sync(&upload_dir, &meta_file).ok()?;
+            Some((upload_dir, meta))
+        })
+        .collect()
+}
+
 /// Reset a chunk back to `NotStarted`, but **only** if it is still `Ongoing`.
 /// The defensive check guards against the race where a retry has already
 /// arrived and re-completed the chunk before this cleanup runs.
-async fn reset_ongoing_chunk_to_not_started(file_path: PathBuf, chunk_index: usize) {
-    let _ = ResumableUploadedFileMeta::update_meta_file(file_path, move |meta| {
-        if let Some(status) = meta.chunks.get(&chunk_index)
+async fn reset_ongoing_chunk_to_not_started(upload_dir: PathBuf, chunk_index: usize) {
+    let _ = ResumableUploadedFileMeta::update_meta_file(upload_dir, move |meta| {
+        if let Some(status) = meta.chunks.get(chunk_index)
             && status.is_ongoing()
         {
-            meta.chunks.insert(chunk_index, ChunkStatus::NotStarted);
+            meta.chunks.set(chunk_index, ChunkStatus::NotStarted);
         }
         Ok(())
     })
@@ -200,24 +603,24 @@ async fn reset_ongoing_chunk_to_not_started(file_path: PathBuf, chunk_index: usi
 /// Note: in release builds `panic = "abort"` skips `Drop` entirely, and
 /// SIGKILL / power loss bypass it too — those cases are covered by
 /// `reset_stale_ongoing_chunks` at startup.
-struct OngoingChunkGuard {
-    file_path: PathBuf,
+pub struct OngoingChunkGuard {
+    upload_dir: PathBuf,
     chunk_index: usize,
     runtime: tokio::runtime::Handle,
     committed: bool,
 }
 
 impl OngoingChunkGuard {
-    fn new(file_path: PathBuf, chunk_index: usize) -> Self {
+    pub fn new(upload_dir: PathBuf, chunk_index: usize) -> Self {
         Self {
-            file_path,
+            upload_dir,
             chunk_index,
             runtime: tokio::runtime::Handle::current(),
             committed: false,
         }
     }
 
-    fn commit(mut self) {
+    pub fn commit(mut self) {
         self.committed = true;
     }
 }
@@ -227,37 +630,34 @@ impl Drop for OngoingChunkGuard {
         if self.committed {
             return;
         }
-        let file_path = std::mem::take(&mut self.file_path);
+        let upload_dir = std::mem::take(&mut self.upload_dir);
         let chunk_index = self.chunk_index;
         self.runtime
-            .spawn(reset_ongoing_chunk_to_not_started(file_path, chunk_index));
+            .spawn(reset_ongoing_chunk_to_not_started(upload_dir, chunk_index));
     }
 }
 
-/// Recursively walk `root`, resetting every `Ongoing` chunk in every
-/// `.resumable-meta` file back to `NotStarted`. Run at process startup,
-/// **before** the listener accepts traffic, so concurrent uploads cannot race
-/// the cleanup. Tolerates a missing root (fresh install) and per-file errors
-/// (corrupt meta, permission denied) — they are logged and skipped.
-pub async fn reset_stale_ongoing_chunks(root: &StdPath) {
-    let root = root.to_path_buf();
-    let meta_paths = match spawn_blocking(move || find_meta_files(&root)).await {
-        Ok(paths) => paths,
+/// Reset every `Ongoing` chunk of every upload staged under `staging_root`
+/// back to `NotStarted`. Run at process startup, **before** the listener
+/// accepts traffic, so concurrent uploads cannot race the cleanup. Tolerates
+/// a missing staging root (fresh install) and per-upload errors (corrupt
+/// meta, permission denied) — they are logged and skipped.
+pub async fn reset_stale_ongoing_chunks(staging_root: &StdPath) {
+    let staging_root = staging_root.to_path_buf();
+    let upload_dirs = match spawn_blocking(move || find_upload_dirs(&staging_root)).await {
+        Ok(dirs) => dirs,
         Err(err) => {
-            eprintln!("startup scan: failed to walk store path: {err}");
+            eprintln!("startup scan: failed to read staging directory: {err}");
             return;
         }
     };
 
-    for meta_path in meta_paths {
-        let Some(file_name) = meta_path.file_stem() else {
-            continue;
-        };
-        let original_path = meta_path.with_file_name(file_name);
-        if let Err(err) = ResumableUploadedFileMeta::update_meta_file(&original_path, |meta| {
-            for status in meta.chunks.values_mut() {
-                if status.is_ongoing() {
-                    *status = ChunkStatus::NotStarted;
+    for upload_dir in upload_dirs {
+        let meta_path = ResumableUploadedFileMeta::path(&upload_dir);
+        if let Err(err) = ResumableUploadedFileMeta::update_meta_file(&upload_dir, |meta| {
+            for chunk_index in 0..meta.chunks.len() {
+                if meta.chunks.get(chunk_index) == Some(ChunkStatus::Ongoing) {
+                    meta.chunks.set(chunk_index, ChunkStatus::NotStarted);
                 }
             }
             Ok(())
@@ -273,26 +673,173 @@ pub async fn reset_stale_ongoing_chunks(root: &StdPath) {
     }
 }
 
-fn find_meta_files(root: &StdPath) -> Vec<PathBuf> {
-    let mut metas = Vec::new();
-    let mut stack = vec![root.to_path_buf()];
-    while let Some(dir) = stack.pop() {
-        let Ok(entries) = std::fs::read_dir(&dir) else {
-            continue;
-        };
-        for entry in entries.flatten() {
-            let Ok(file_type) = entry.file_type() else {
-                continue;
-            };
-            let path = entry.path();
-            if file_type.is_dir() {
-                stack.push(path);
-            } else if path.extension().and_then(|s| s.to_str()) == Some("resumable-meta") {
-                metas.push(path);
+/// Every directory directly under `staging_root` that holds a meta file.
+fn find_upload_dirs(staging_root: &StdPath) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(staging_root) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+        .map(|entry| entry.path())
+        .filter(|upload_dir| ResumableUploadedFileMeta::path(upload_dir).is_file())
+        .collect()
+}
+
+/// When the upload in `upload_dir` last made progress: the latest
+/// modification time of the directory, its meta, its chunk map and its data.
+/// Every chunk write touches the data file and every chunk status change
+/// writes to the chunk map, so this is the time of the last chunk activity.
+pub fn last_activity(upload_dir: &StdPath) -> Option<SystemTime> {
+    [
+        upload_dir.to_path_buf(),
+        ResumableUploadedFileMeta::path(upload_dir),
+        chunk_map_path(upload_dir),
+        data_path(upload_dir),
+    ]
+    .iter()
+    .filter_map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+    .max()
+}
+
+/// Remove every upload staged under `staging_root` that has seen no chunk
+/// activity for `ttl`, and return the directories that were removed. Uploads
+/// that were claimed but never got a meta (the server died inside `post`) are
+/// swept the same way. Failures are logged and skipped.
+///
+/// An upload is only removed while holding the lock of its meta, after checking
+/// again that it expired and none of its chunks is `Ongoing`, so a chunk
+/// request arriving just in time either keeps it or finds it gone.
+fn sweep_expired_uploads_sync(staging_root: &StdPath, ttl: Duration) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(staging_root) else {
+        return Vec::new();
+    };
+    let is_expired = |upload_dir: &StdPath| {
+        last_activity(upload_dir).is_some_and(|last_activity| {
+            SystemTime::now()
+                .duration_since(last_activity)
+                .is_ok_and(|idle| idle >= ttl)
+        })
+    };
+
+    entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+        .map(|entry| entry.path())
+        .filter(|upload_dir| is_expired(upload_dir))
+        .filter(|upload_dir| {
+            let meta_file = StdFile::open(ResumableUploadedFileMeta::path(upload_dir)).ok();
+            if let Some(meta_file) = &meta_file
+                && flock(meta_file.as_fd(), FlockOperation::LockExclusive).is_err()
+            {
+                return false;
+            }
+            let meta = meta_file.as_ref().and_then(|meta_file| {
+                ResumableUploadedFileMeta::read_from_file_sync(upload_dir, meta_file).ok()
+            });
+            if !is_expired(upload_dir)
+                || meta
+                    .as_ref()
+                    .is_some_and(|meta| meta.chunks.iter().any(|status| status.is_ongoing()))
+            {
+                return false;
+            }
+            let target = meta
+                .map(|meta| meta.target)
+                .filter(|target| !target.is_empty());
+
+            match std::fs::remove_dir_all(upload_dir) {
+                Ok(()) => {
+                    match target {
+                        Some(target) => eprintln!(
+                            "upload sweep: removed expired upload of `{target}` ({})",
+                            upload_dir.display()
+                        ),
+                        None => eprintln!(
+                            "upload sweep: removed expired upload {}",
+                            upload_dir.display()
+                        ),
+                    }
+                    true
+                }
+                Err(err) => {
+                    eprintln!(
+                        "upload sweep: failed to remove {}: {err}",
+                        upload_dir.display()
+                    );
+                    false
+                }
             }
+        })
+        .collect()
+}
+
+/// See [`sweep_expired_uploads_sync`].
+pub async fn sweep_expired_uploads(staging_root: &StdPath, ttl: Duration) -> Vec<PathBuf> {
+    let staging_root = staging_root.to_path_buf();
+    spawn_blocking(move || sweep_expired_uploads_sync(&staging_root, ttl))
+        .await
+        .unwrap_or_default()
+}
+
+/// Sweep the expired uploads of the store at `store_path`, both the staged
+/// and the quarantined ones. Quarantined uploads expire like abandoned ones,
+/// a TTL after their last chunk arrived.
+pub async fn sweep_expired_uploads_in_store(store_path: &StdPath, ttl: Duration) -> Vec<PathBuf> {
+    let mut removed = sweep_expired_uploads(&staging_root(store_path), ttl).await;
+    removed.extend(sweep_expired_uploads(&quarantine_root(store_path), ttl).await);
+    removed
+}
+
+/// Sweep expired uploads and ended share links now and then every `ttl` or
+/// [`SWEEP_INTERVAL`], whichever is shorter, for as long as the server runs.
+/// An upload is therefore removed at most one interval after it expires. A
+/// `ttl` of zero keeps uploads forever, so only share links are swept.
+pub async fn sweep_expired_periodically(store_path: PathBuf, ttl: Duration) {
+    let period = if ttl.is_zero() {
+        SWEEP_INTERVAL
+    } else {
+        ttl.min(SWEEP_INTERVAL)
+    };
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        if !ttl.is_zero() {
+            sweep_expired_uploads_in_store(&store_path, ttl).await;
         }
+        share::sweep_ended_shares(&store_path).await;
+    }
+}
+
+/// Whether `path` is one of the bookkeeping files older versions kept next to
+/// the target of a resumable upload, which are never listed or served. Uploads
+/// are staged under [`STATE_DIR_NAME`] now. The leftovers always come in pairs,
+/// so a file that merely ends in `.resumable-meta` without its
+/// `.resumable-upload` sibling, or the other way around, is a user's own.
+pub async fn is_upload_artifact(path: &StdPath) -> bool {
+    match artifact_sibling(path) {
+        Some(sibling) => fs::metadata(sibling)
+            .await
+            .is_ok_and(|metadata| metadata.is_file()),
+        None => false,
     }
-    metas
+}
+
+/// See [`is_upload_artifact`].
+pub fn is_upload_artifact_sync(path: &StdPath) -> bool {
+    artifact_sibling(path).is_some_and(|sibling| sibling.is_file())
+}
+
+/// The other file of the pair `path` would belong to if it were a leftover of
+/// an upload, see [`is_upload_artifact`].
+fn artifact_sibling(path: &StdPath) -> Option<PathBuf> {
+    let (target, extension) = path.file_name()?.to_str()?.rsplit_once('.')?;
+    let sibling = match extension {
+        "resumable-meta" => "resumable-upload",
+        "resumable-upload" => "resumable-meta",
+        _ => return None,
+    };
+    Some(path.with_file_name(format!("{target}.{sibling}")))
 }
 
 fn describe_server_error(err: &ServerError) -> &'static str {
@@ -305,60 +852,215 @@ fn describe_server_error(err: &ServerError) -> &'static str {
 }
 
 /// Get the meta information of a resumable uploaded file.
-pub async fn get(_: Claims, Path(path): Path<String>) -> Result<impl IntoResponse, ServerError> {
-    let file_path = safe_join(&CONFIG.store_path, &path)?;
-
-    if let Some(meta) = ResumableUploadedFileMeta::read_from_file(&file_path).await? {
-        Ok(Json(meta).into_response())
+pub async fn get(
+    claims: Claims,
+    Path(path): Path<String>,
+) -> Result<impl IntoResponse, ServerError> {
+    let file_path = safe_join(claims.root(), &path)?;
+    claims.authorize(Capability::Write, &file_path)?;
+    let upload_dir = upload_dir(&CONFIG.store_path, &relative_target(&file_path));
+
+    if let Some(mut meta) = ResumableUploadedFileMeta::read_from_file(&upload_dir).await? {
+        // Users only know the paths inside their root.
+        meta.target = relative_path(&file_path, claims.root());
+        Ok(Json(meta.status()).into_response())
     } else {
         Ok(StatusCode::NOT_FOUND.into_response())
     }
 }
 
+/// Cancel a resumable upload, removing everything staged for it.
+pub async fn delete(claims: Claims, Path(path): Path<String>) -> Result<StatusCode, ServerError> {
+    let file_path = safe_join(claims.root(), &path)?;
+    claims.authorize(Capability::Write, &file_path)?;
+    let upload_dir = upload_dir(&CONFIG.store_path, &relative_target(&file_path));
+
+    remove_upload(upload_dir).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Remove the upload staged in `upload_dir`, unless one of its chunks is
+/// `Ongoing`. Holds the meta file lock from the check until the directory is
+/// gone, so no chunk can start in between. A chunk that was already waiting
+/// for the lock then finds its data file missing and fails.
+pub async fn remove_upload(upload_dir: PathBuf) -> Result<(), ServerError> {
+    spawn_blocking(move || {
+        let meta_file = match StdFile::open(ResumableUploadedFileMeta::path(&upload_dir)) {
+            Ok(meta_file) => meta_file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Err(ServerError::FileIsNotCreated);
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        flock(meta_file.as_fd(), FlockOperation::LockExclusive)?;
+
+        // A broken meta is no reason to keep the upload around.
+        if let Ok(meta) = ResumableUploadedFileMeta::read_from_file_sync(&upload_dir, &meta_file)
+            && meta.chunks.iter().any(|status| status.is_ongoing())
+        {
+            return Err(ServerError::UploadHasOngoingChunks);
+        }
+
+        std::fs::remove_dir_all(&upload_dir)?;
+
+        Ok(())
+    })
+    .await?
+}
+
+fn legacy_conflict_policy() -> ConflictPolicy {
+    ConflictPolicy::Overwrite
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateResumableUploadFileRequest {
     pub size: u64,
+
+    /// Chunk size the client would like to upload in, clamped to the
+    /// configured bounds. The meta in the response has the one that applies.
+    pub chunk_size: Option<usize>,
+
+    /// Overrides the configured conflict policy for this upload.
+    pub conflict: Option<ConflictPolicy>,
+
+    /// Expected hash of the complete file, verified once all chunks arrived.
+    pub hash: Option<FileHash>,
 }
 
 /// Create a resumable upload file.
 pub async fn post(
-    _: Claims,
+    claims: Claims,
     Path(path): Path<String>,
     request: Json<CreateResumableUploadFileRequest>,
 ) -> Result<impl IntoResponse, ServerError> {
-    let file_path = safe_join(&CONFIG.store_path, &path)?;
+    let file_path = safe_join(claims.root(), &path)?;
+    claims.authorize(Capability::Write, &file_path)?;
+
+    let chunk_size = negotiate_chunk_size(
+        request.chunk_size,
+        CONFIG.chunk_size,
+        CONFIG.min_chunk_size,
+        CONFIG.max_chunk_size,
+    );
+    let mut upload_meta = ResumableUploadedFileMeta::new(chunk_size, request.size);
+    upload_meta.conflict = files::allowed_conflict_policy(
+        &claims,
+        request.conflict.unwrap_or(CONFIG.upload_conflict),
+        &file_path,
+    );
+    upload_meta.hash = request.hash;
+    create_upload(&file_path, &mut upload_meta).await?;
+    upload_meta.target = relative_path(&file_path, claims.root());
+
+    Ok((StatusCode::CREATED, Json(upload_meta.status())).into_response())
+}
+
+/// The chunk size of a new upload: the `requested` one within `min..=max`, or
+/// `default` when the client didn't ask for one. `max` never exceeds the
+/// upload byte budget, so a chunk of any negotiated size can be accepted.
+fn negotiate_chunk_size(requested: Option<usize>, default: usize, min: usize, max: usize) -> usize {
+    requested.map_or(default, |requested| requested.clamp(min, max))
+}
 
-    if ResumableUploadedFileMeta::exists(&file_path)? {
-        return Err(ServerError::FileAlreadyExists);
+/// Stage a new upload of `file_path` described by `meta`, recording the
+/// target in it, and return its upload directory.
+pub async fn create_upload(
+    file_path: &StdPath,
+    meta: &mut ResumableUploadedFileMeta,
+) -> Result<PathBuf, ServerError> {
+    meta.target = relative_target(file_path);
+    let upload_dir = upload_dir(&CONFIG.store_path, &meta.target);
+
+    // Fail early rather than after the whole file went over the wire. The
+    // check is repeated atomically when the upload is moved into place.
+    if meta.conflict == ConflictPolicy::Reject && fs::try_exists(file_path).await? {
+        return Err(ServerError::FileConflict);
+    }
+
+    // Creating the directory is what claims the upload ID, so two requests
+    // racing to start the same upload can't both succeed.
+    fs::create_dir_all(staging_root(&CONFIG.store_path)).await?;
+    match fs::create_dir(&upload_dir).await {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+            return Err(ServerError::FileAlreadyExists);
+        }
+        Err(err) => return Err(err.into()),
     }
 
-    let upload_meta = ResumableUploadedFileMeta::new(CONFIG.chunk_size, request.size);
-    let upload_meta_file_path = ResumableUploadedFileMeta::path(&file_path);
+    let upload_meta_file_path = ResumableUploadedFileMeta::path(&upload_dir);
     let upload_meta_file_content =
-        serde_json::to_string(&upload_meta).map_err(|err| ServerError::Custom {
+        serde_json::to_string(&meta).map_err(|err| ServerError::Custom {
             status: StatusCode::INTERNAL_SERVER_ERROR,
             message: err.to_string(),
         })?;
+    let upload_file_path = data_path(&upload_dir);
+    let upload_file = fs::File::create(&upload_file_path).await?;
+    let file_size = meta.file_size;
+    // `fallocate` refuses a zero length, and an empty file has nothing to reserve.
+    if file_size > 0 {
+        spawn_blocking(move || {
+            fallocate(upload_file.as_fd(), FallocateFlags::empty(), 0, file_size)
+        })
+        .await??;
+    }
+    meta.write_chunk_map(&upload_dir).await?;
+    // Written last: a staging directory without a meta is ignored everywhere,
+    // so a crash above never leaves a half-created upload behind.
     fs::write(&upload_meta_file_path, upload_meta_file_content).await?;
 
-    let upload_file_name = format!(
-        "{}.resumable-upload",
-        file_path.file_name().unwrap().to_str().unwrap()
-    );
-    let upload_file_path = file_path.with_file_name(upload_file_name);
-    let upload_file = fs::File::create(&upload_file_path).await?;
-    spawn_blocking(move || {
-        fallocate(
-            upload_file.as_fd(),
-            FallocateFlags::empty(),
-            0,
-            request.size,
-        )
-    })
-    .await??;
+    Ok(upload_dir)
+}
+
+/// Move the completed upload in `upload_dir` to `file_path` according to its
+/// conflict policy, and return the path relative to `root` it was stored at.
+/// An upload that doesn't match its expected hash is quarantined instead.
+pub async fn finish_upload(
+    upload_dir: &StdPath,
+    file_path: &StdPath,
+    meta: &ResumableUploadedFileMeta,
+    root: &StdPath,
+) -> Result<String, ServerError> {
+    let upload_file_path = data_path(upload_dir);
+
+    if let Some(hash) = meta.hash {
+        let data = upload_file_path.clone();
+        if !spawn_blocking(move || hash.matches_file(&data)).await?? {
+            let quarantined = quarantine(upload_dir, &quarantine_root(&CONFIG.store_path)).await?;
+            eprintln!(
+                "upload of `{}` does not match its expected hash, quarantined in {}",
+                meta.target,
+                quarantined.display()
+            );
+            return Err(ServerError::FileHashMismatch);
+        }
+    }
+
+    // Folder uploads target files in subdirectories that don't exist yet.
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let stored_path =
+        match conflict::place(upload_file_path, file_path.to_path_buf(), meta.conflict).await {
+            Ok(stored_path) => stored_path,
+            // Every chunk is completed, so the upload could neither be resumed
+            // nor restarted; drop it and let the client start over.
+            Err(ServerError::FileConflict) => {
+                fs::remove_dir_all(upload_dir).await?;
+                return Err(ServerError::FileConflict);
+            }
+            Err(err) => return Err(err),
+        };
+    fs::remove_dir_all(upload_dir).await?;
 
-    Ok((StatusCode::CREATED, Json(upload_meta)))
+    Ok(stored_path
+        .strip_prefix(root)
+        .unwrap_or(&stored_path)
+        .to_string_lossy()
+        .into_owned())
 }
 
 #[derive(Serialize)]
@@ -366,30 +1068,33 @@ pub async fn post(
 pub struct ResumableUploadFileResponse {
     pub success: bool,
     pub all_chunks_completed: bool,
+
+    /// Where the finished file was stored, relative to the user's root. Only
+    /// set once all chunks are completed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
 }
 impl ResumableUploadFileResponse {
     pub fn new(success: bool, all_chunks_completed: bool) -> Self {
         Self {
             success,
             all_chunks_completed,
+            path: None,
         }
     }
 }
 
 /// Create a resumable upload file.
 pub async fn put(
-    _: Claims,
+    claims: Claims,
     Path(path): Path<String>,
     headers: HeaderMap,
     body: Body,
 ) -> Result<impl IntoResponse, ServerError> {
-    let file_path = safe_join(&CONFIG.store_path, &path)?;
-
-    let upload_file_name = format!(
-        "{}.resumable-upload",
-        file_path.file_name().unwrap().to_str().unwrap()
-    );
-    let upload_file_path = file_path.with_file_name(upload_file_name);
+    let file_path = safe_join(claims.root(), &path)?;
+    claims.authorize(Capability::Write, &file_path)?;
+    let upload_dir = upload_dir(&CONFIG.store_path, &relative_target(&file_path));
+    let upload_file_path = data_path(&upload_dir);
 
     let chunk_index: usize = headers
         .get("resumable-upload-chunk-index")
@@ -407,33 +1112,38 @@ pub async fn put(
         .parse()
         .map_err(|_| ServerError::InvalidContentLength)?;
 
-    let meta = ResumableUploadedFileMeta::read_from_file(&file_path)
+    let expected_digest = expected_chunk_digest(&headers)?;
+
+    let meta = ResumableUploadedFileMeta::read_from_file(&upload_dir)
         .await?
         .ok_or(ServerError::FileIsNotCreated)?;
+    if meta.offset.is_some() {
+        return Err(ServerError::NotAChunkedUpload);
+    }
 
     // `chunk_size` and `chunks.len()` are fixed at meta creation, so validating
     // them outside the lock is safe. The chunk *status* check has to happen
     // atomically with the transition to `Ongoing`, so it lives in the closure
     // passed to `update_meta_file`.
+    if chunk_index >= meta.chunks.len() {
+        return Err(ServerError::InvalidChunkIndex);
+    }
     if content_length != meta.chunk_size
         && !(chunk_index == meta.chunks.len() - 1 && content_length < meta.chunk_size)
     {
         return Err(ServerError::InvalidContentLength);
     }
-    if !meta.chunks.contains_key(&chunk_index) {
-        return Err(ServerError::InvalidChunkIndex);
-    }
 
-    let _upload_permits = UploadPermits::acquire(&file_path, content_length)?;
+    let _upload_permits = UploadPermits::acquire(&upload_dir, content_length)?;
 
-    ResumableUploadedFileMeta::update_meta_file(&file_path, move |meta| {
-        match meta.chunks.get(&chunk_index) {
+    ResumableUploadedFileMeta::update_meta_file(&upload_dir, move |meta| {
+        match meta.chunks.get(chunk_index) {
             None => return Err(ServerError::InvalidChunkIndex),
             Some(s) if s.is_ongoing() => return Err(ServerError::ChunkIsOngoing),
             Some(s) if s.is_completed() => return Err(ServerError::ChunkIsCompleted),
             Some(_) => {}
         }
-        meta.chunks.insert(chunk_index, ChunkStatus::Ongoing);
+        meta.chunks.set(chunk_index, ChunkStatus::Ongoing);
         Ok(())
     })
     .await?;
@@ -442,7 +1152,7 @@ pub async fn put(
     // shutdown), Drop spawns a task to reset the chunk back to NotStarted.
     // MUST be constructed synchronously here — no `.await` between the
     // transition to Ongoing and this line, or there's a cancellation window.
-    let guard = OngoingChunkGuard::new(file_path.clone(), chunk_index);
+    let guard = OngoingChunkGuard::new(upload_dir.clone(), chunk_index);
 
     let upload_file = fs::OpenOptions::new()
         .write(true)
@@ -455,42 +1165,49 @@ pub async fn put(
         Arc::new(upload_file),
         (chunk_index * meta.chunk_size) as u64,
         content_length,
+        expected_digest,
         body,
     )
     .await;
 
-    if write_result.is_err() {
-        ResumableUploadedFileMeta::update_meta_file(&file_path, move |meta| {
-            meta.chunks.insert(chunk_index, ChunkStatus::NotStarted);
+    if let Err(err) = write_result {
+        ResumableUploadedFileMeta::update_meta_file(&upload_dir, move |meta| {
+            meta.chunks.set(chunk_index, ChunkStatus::NotStarted);
             Ok(())
         })
         .await?;
         guard.commit();
-        return Err(ServerError::UploadChunkFailed);
+        return Err(match err {
+            ServerError::ChunkDigestMismatch => err,
+            _ => ServerError::UploadChunkFailed,
+        });
     }
 
-    ResumableUploadedFileMeta::update_meta_file(&file_path, move |meta| {
-        meta.chunks.insert(chunk_index, ChunkStatus::Completed);
+    ResumableUploadedFileMeta::update_meta_file(&upload_dir, move |meta| {
+        meta.chunks.set(chunk_index, ChunkStatus::Completed);
         Ok(())
     })
     .await?;
     guard.commit();
 
-    // Check if all chunks are completed
-    let meta = ResumableUploadedFileMeta::read_from_file(&file_path)
+    // Check if all chunks are completed. The upload may have been cancelled
+    // since the chunk was committed.
+    let meta = ResumableUploadedFileMeta::read_from_file(&upload_dir)
         .await?
-        .unwrap();
-    if meta.chunks.values().all(|status| status.is_completed()) {
-        fs::rename(upload_file_path, &file_path).await?;
-        fs::remove_file(ResumableUploadedFileMeta::path(&file_path)).await?;
+        .ok_or(ServerError::FileIsNotCreated)?;
+    if meta.chunks.iter().all(|status| status.is_completed()) {
+        let stored_path = finish_upload(&upload_dir, &file_path, &meta, claims.root()).await?;
 
-        Ok(Json(ResumableUploadFileResponse::new(true, true)))
+        Ok(Json(ResumableUploadFileResponse {
+            path: Some(stored_path),
+            ..ResumableUploadFileResponse::new(true, true)
+        }))
     } else {
         Ok(Json(ResumableUploadFileResponse::new(true, false)))
     }
 }
 
-struct UploadPermits {
+pub struct UploadPermits {
     // Fields are held only for their `Drop` side effect (releasing the permits
     // back to their respective semaphores when this struct goes out of scope).
     _global_permit: OwnedSemaphorePermit,
@@ -499,7 +1216,7 @@ struct UploadPermits {
 }
 
 impl UploadPermits {
-    fn acquire(upload_path: &StdPath, content_length: usize) -> Result<Self, ServerError> {
+    pub fn acquire(upload_path: &StdPath, content_length: usize) -> Result<Self, ServerError> {
         let global_permit = ACTIVE_UPLOAD_CHUNKS
             .clone()
             .try_acquire_owned()
@@ -559,14 +1276,62 @@ fn byte_budget_permits(byte_count: usize) -> u32 {
         .min(u32::MAX as usize) as u32
 }
 
+/// The SHA-256 digest the client declared for a chunk, taken from the
+/// RFC 9530 `Content-Digest` header (`sha-256=:<base64>:`) or from the
+/// `Resumable-Upload-Chunk-Sha256` header (hex). `None` when the client sent
+/// neither, or only digests with algorithms other than SHA-256.
+fn expected_chunk_digest(headers: &HeaderMap) -> Result<Option<[u8; 32]>, ServerError> {
+    let mut expected = None;
+
+    if let Some(value) = headers.get("resumable-upload-chunk-sha256") {
+        let hex = value
+            .to_str()
+            .map_err(|_| ServerError::InvalidChunkDigest)?
+            .trim();
+        expected = Some(parse_hex_digest(hex).ok_or(ServerError::InvalidChunkDigest)?);
+    }
+
+    for value in headers.get_all("content-digest") {
+        let value = value
+            .to_str()
+            .map_err(|_| ServerError::InvalidChunkDigest)?;
+        for member in value.split(',') {
+            let (algorithm, digest) = member
+                .trim()
+                .split_once('=')
+                .ok_or(ServerError::InvalidChunkDigest)?;
+            if algorithm != "sha-256" {
+                continue;
+            }
+            let digest = digest
+                .strip_prefix(':')
+                .and_then(|digest| digest.strip_suffix(':'))
+                .and_then(|digest| BASE64.decode(digest).ok())
+                .and_then(|digest| <[u8; 32]>::try_from(digest).ok())
+                .ok_or(ServerError::InvalidChunkDigest)?;
+            // Two headers disagreeing can't both be right.
+            if expected.is_some_and(|expected| expected != digest) {
+                return Err(ServerError::InvalidChunkDigest);
+            }
+            expected = Some(digest);
+        }
+    }
+
+    Ok(expected)
+}
+
+/// Write `body` to `file` at `offset`, failing unless it is exactly
+/// `content_length` bytes long and, when given, hashes to `expected_digest`.
 async fn stream_body_to_file(
     file: Arc<StdFile>,
     offset: u64,
     content_length: usize,
+    expected_digest: Option<[u8; 32]>,
     body: Body,
 ) -> Result<(), ServerError> {
     let mut body = body.into_data_stream();
     let mut total_written = 0usize;
+    let mut hasher = expected_digest.map(|_| Sha256::new());
 
     while let Some(chunk) = body.next().await {
         let chunk = chunk.map_err(|err| ServerError::Custom {
@@ -579,6 +1344,10 @@ async fn stream_body_to_file(
             return Err(ServerError::InvalidContentLength);
         }
 
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&chunk);
+        }
+
         let file = file.clone();
         let chunk_offset = offset + total_written as u64;
         spawn_blocking(move || file_seek_write_all(&file, chunk_offset, &chunk)).await??;
@@ -589,12 +1358,18 @@ async fn stream_body_to_file(
         return Err(ServerError::InvalidContentLength);
     }
 
+    if let (Some(hasher), Some(expected_digest)) = (hasher, expected_digest)
+        && hasher.finalize()[..] != expected_digest
+    {
+        return Err(ServerError::ChunkDigestMismatch);
+    }
+
     Ok(())
 }
 
 #[cfg(not(windows))]
 #[inline]
-fn file_seek_write_all(file: &StdFile, offset: u64, data: &[u8]) -> Result<(), ServerError> {
+pub fn file_seek_write_all(file: &StdFile, offset: u64, data: &[u8]) -> Result<(), ServerError> {
     let mut total_written = 0;
 
     while total_written < data.len() {
@@ -610,7 +1385,7 @@ fn file_seek_write_all(file: &StdFile, offset: u64, data: &[u8]) -> Result<(), S
 }
 #[cfg(windows)]
 #[inline]
-fn file_seek_write_all(file: &StdFile, offset: u64, data: &[u8]) -> Result<(), ServerError> {
+pub fn file_seek_write_all(file: &StdFile, offset: u64, data: &[u8]) -> Result<(), ServerError> {
     use std::os::windows::fs::FileExt;
     let mut total_written = 0;
 
@@ -630,16 +1405,18 @@ mod tests {
 
     // ----- helpers -----
 
-    fn write_meta_raw(file_path: &StdPath, meta: &ResumableUploadedFileMeta) {
-        let meta_path = ResumableUploadedFileMeta::path(file_path);
+    fn write_meta_raw(upload_dir: &StdPath, meta: &ResumableUploadedFileMeta) {
+        std::fs::create_dir_all(upload_dir).unwrap();
+        let meta_path = ResumableUploadedFileMeta::path(upload_dir);
         let bytes = serde_json::to_vec(meta).unwrap();
         std::fs::write(meta_path, bytes).unwrap();
+        std::fs::write(chunk_map_path(upload_dir), meta.chunks.as_bytes()).unwrap();
     }
 
-    fn read_meta_raw(file_path: &StdPath) -> ResumableUploadedFileMeta {
-        let meta_path = ResumableUploadedFileMeta::path(file_path);
+    fn read_meta_raw(upload_dir: &StdPath) -> ResumableUploadedFileMeta {
+        let meta_path = ResumableUploadedFileMeta::path(upload_dir);
         let file = StdFile::open(meta_path).unwrap();
-        serde_json::from_reader(BufReader::new(file)).unwrap()
+        ResumableUploadedFileMeta::read_from_file_sync(upload_dir, &file).unwrap()
     }
 
     fn fixture(chunks: &[ChunkStatus]) -> ResumableUploadedFileMeta {
@@ -647,7 +1424,7 @@ mod tests {
         let file_size = (chunks.len() * chunk_size) as u64;
         let mut meta = ResumableUploadedFileMeta::new(chunk_size, file_size);
         for (i, status) in chunks.iter().enumerate() {
-            meta.chunks.insert(i, *status);
+            meta.chunks.set(i, *status);
         }
         meta
     }
@@ -662,6 +1439,47 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn recognises_upload_artifacts() {
+        let dir = TempDir::new().unwrap();
+        for name in [
+            "a.txt.resumable-meta",
+            "a.txt.resumable-upload",
+            "notes.resumable-meta",
+            "notes.form-upload",
+            "resumable-meta",
+        ] {
+            std::fs::write(dir.path().join(name), b"").unwrap();
+        }
+
+        assert!(is_upload_artifact_sync(
+            &dir.path().join("a.txt.resumable-meta")
+        ));
+        assert!(is_upload_artifact_sync(
+            &dir.path().join("a.txt.resumable-upload")
+        ));
+        assert!(!is_upload_artifact_sync(&dir.path().join("a.txt")));
+        // A user's own files without the sibling of an upload.
+        assert!(!is_upload_artifact_sync(
+            &dir.path().join("notes.resumable-meta")
+        ));
+        assert!(!is_upload_artifact_sync(
+            &dir.path().join("notes.form-upload")
+        ));
+        assert!(!is_upload_artifact_sync(&dir.path().join("resumable-meta")));
+
+        assert!(is_upload_artifact(&dir.path().join("a.txt.resumable-meta")).await);
+        assert!(!is_upload_artifact(&dir.path().join("notes.resumable-meta")).await);
+    }
+
+    #[test]
+    fn progress_counts_completed_chunks() {
+        use ChunkStatus::*;
+        assert_eq!(fixture(&[]).progress(), 0);
+        assert_eq!(fixture(&[NotStarted, Ongoing, Completed]).progress(), 33);
+        assert_eq!(fixture(&[Completed, Completed]).progress(), 100);
+    }
+
     // ----- ResumableUploadedFileMeta::new chunk-count math -----
 
     #[test]
@@ -675,7 +1493,7 @@ mod tests {
     fn new_single_byte_yields_one_chunk() {
         let meta = ResumableUploadedFileMeta::new(8 * 1024 * 1024, 1);
         assert_eq!(meta.chunks.len(), 1);
-        assert_eq!(meta.chunks[&0], ChunkStatus::NotStarted);
+        assert_eq!(meta.chunks.get(0).unwrap(), ChunkStatus::NotStarted);
     }
 
     #[test]
@@ -690,6 +1508,13 @@ mod tests {
         assert_eq!(meta.chunks.len(), 2);
     }
 
+    #[test]
+    fn meta_without_conflict_policy_overwrites() {
+        let meta: ResumableUploadedFileMeta =
+            serde_json::from_str(r#"{"chunkSize":8,"fileSize":8,"chunks":{"0":0}}"#).unwrap();
+        assert_eq!(meta.conflict, ConflictPolicy::Overwrite);
+    }
+
     #[test]
     fn new_exact_two_chunks() {
         let meta = ResumableUploadedFileMeta::new(8 * 1024 * 1024, 16 * 1024 * 1024);
@@ -702,33 +1527,30 @@ mod tests {
         assert_eq!(meta.chunks.len(), 3);
     }
 
-    // ----- path round-trip via file_stem -----
+    // ----- staging layout -----
 
     #[test]
-    fn meta_path_round_trip_with_extension() {
-        let original = StdPath::new("/store/foo.txt");
-        let meta = ResumableUploadedFileMeta::path(original);
-        assert_eq!(meta, StdPath::new("/store/foo.txt.resumable-meta"));
-        let derived = meta.with_file_name(meta.file_stem().unwrap());
-        assert_eq!(derived, original);
+    fn meta_lives_inside_upload_dir() {
+        let upload_dir = StdPath::new("/store/.sfs/uploads/abc");
+        assert_eq!(
+            ResumableUploadedFileMeta::path(upload_dir),
+            StdPath::new("/store/.sfs/uploads/abc/meta.json")
+        );
+        assert_eq!(
+            data_path(upload_dir),
+            StdPath::new("/store/.sfs/uploads/abc/data")
+        );
     }
 
     #[test]
-    fn meta_path_round_trip_no_extension() {
-        let original = StdPath::new("/store/foo");
-        let meta = ResumableUploadedFileMeta::path(original);
-        assert_eq!(meta, StdPath::new("/store/foo.resumable-meta"));
-        let derived = meta.with_file_name(meta.file_stem().unwrap());
-        assert_eq!(derived, original);
-    }
+    fn upload_dir_is_keyed_by_target() {
+        let store = StdPath::new("/store");
+        let a = upload_dir(store, "photos/a.jpg");
 
-    #[test]
-    fn meta_path_round_trip_multi_dot() {
-        let original = StdPath::new("/store/foo.tar.gz");
-        let meta = ResumableUploadedFileMeta::path(original);
-        assert_eq!(meta, StdPath::new("/store/foo.tar.gz.resumable-meta"));
-        let derived = meta.with_file_name(meta.file_stem().unwrap());
-        assert_eq!(derived, original);
+        assert_eq!(a, upload_dir(store, "photos/a.jpg"));
+        assert_ne!(a, upload_dir(store, "photos/b.jpg"));
+        assert_eq!(a.parent().unwrap(), staging_root(store));
+        assert_eq!(a.file_name().unwrap().len(), 64);
     }
 
     // ----- update_meta_file -----
@@ -736,29 +1558,32 @@ mod tests {
     #[tokio::test]
     async fn update_meta_file_applies_change() {
         let dir = TempDir::new().unwrap();
-        let file_path = dir.path().join("a.bin");
-        write_meta_raw(&file_path, &fixture(&[ChunkStatus::NotStarted]));
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::NotStarted]));
 
-        ResumableUploadedFileMeta::update_meta_file(file_path.clone(), |meta| {
-            meta.chunks.insert(0, ChunkStatus::Completed);
+        ResumableUploadedFileMeta::update_meta_file(upload_dir.clone(), |meta| {
+            meta.chunks.set(0, ChunkStatus::Completed);
             Ok(())
         })
         .await
         .unwrap();
 
-        assert_eq!(read_meta_raw(&file_path).chunks[&0], ChunkStatus::Completed);
+        assert_eq!(
+            read_meta_raw(&upload_dir).chunks.get(0).unwrap(),
+            ChunkStatus::Completed
+        );
     }
 
     #[tokio::test]
     async fn update_meta_file_does_not_write_when_updater_errs() {
         let dir = TempDir::new().unwrap();
-        let file_path = dir.path().join("a.bin");
-        write_meta_raw(&file_path, &fixture(&[ChunkStatus::NotStarted]));
-        let meta_path = ResumableUploadedFileMeta::path(&file_path);
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::NotStarted]));
+        let meta_path = ResumableUploadedFileMeta::path(&upload_dir);
         let before = std::fs::read(&meta_path).unwrap();
 
-        let result = ResumableUploadedFileMeta::update_meta_file(file_path.clone(), |meta| {
-            meta.chunks.insert(0, ChunkStatus::Ongoing);
+        let result = ResumableUploadedFileMeta::update_meta_file(upload_dir.clone(), |meta| {
+            meta.chunks.set(0, ChunkStatus::Ongoing);
             Err(ServerError::ChunkIsOngoing)
         })
         .await;
@@ -771,16 +1596,16 @@ mod tests {
     #[tokio::test]
     async fn update_meta_file_skips_write_on_noop() {
         let dir = TempDir::new().unwrap();
-        let file_path = dir.path().join("a.bin");
-        write_meta_raw(&file_path, &fixture(&[ChunkStatus::Completed]));
-        let meta_path = ResumableUploadedFileMeta::path(&file_path);
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::Completed]));
+        let meta_path = ResumableUploadedFileMeta::path(&upload_dir);
         let before = std::fs::read(&meta_path).unwrap();
         let before_mtime = std::fs::metadata(&meta_path).unwrap().modified().unwrap();
 
         // Sleep so any spurious write would produce a measurably different mtime.
         tokio::time::sleep(Duration::from_millis(20)).await;
 
-        ResumableUploadedFileMeta::update_meta_file(file_path.clone(), |_meta| Ok(()))
+        ResumableUploadedFileMeta::update_meta_file(upload_dir.clone(), |_meta| Ok(()))
             .await
             .unwrap();
 
@@ -797,22 +1622,22 @@ mod tests {
         // those trailing bytes behind. With the truncate fix the file must
         // shrink back to exactly the serialized length.
         let dir = TempDir::new().unwrap();
-        let file_path = dir.path().join("a.bin");
-        let meta_path = ResumableUploadedFileMeta::path(&file_path);
+        let upload_dir = dir.path().join("upload");
+        let meta_path = ResumableUploadedFileMeta::path(&upload_dir);
 
-        write_meta_raw(&file_path, &fixture(&[ChunkStatus::NotStarted]));
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::NotStarted]));
         let mut padded = std::fs::read(&meta_path).unwrap();
         padded.extend(std::iter::repeat_n(b' ', 64));
         std::fs::write(&meta_path, &padded).unwrap();
 
-        ResumableUploadedFileMeta::update_meta_file(file_path.clone(), |meta| {
-            meta.chunks.insert(0, ChunkStatus::Completed);
+        ResumableUploadedFileMeta::update_meta_file(upload_dir.clone(), |meta| {
+            meta.target = "a.txt".to_string();
             Ok(())
         })
         .await
         .unwrap();
 
-        let expected = serde_json::to_vec(&read_meta_raw(&file_path)).unwrap();
+        let expected = serde_json::to_vec(&read_meta_raw(&upload_dir)).unwrap();
         assert_eq!(
             std::fs::metadata(&meta_path).unwrap().len(),
             expected.len() as u64,
@@ -821,47 +1646,140 @@ mod tests {
         assert_eq!(std::fs::read(&meta_path).unwrap(), expected);
     }
 
-    // ----- State transitions / chunk merge predicate -----
-
     #[tokio::test]
-    async fn cannot_transition_to_ongoing_twice() {
+    async fn update_meta_file_writes_chunk_changes_in_place() {
         let dir = TempDir::new().unwrap();
-        let file_path = dir.path().join("a.bin");
-        write_meta_raw(&file_path, &fixture(&[ChunkStatus::NotStarted]));
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::NotStarted; 9]));
+        let meta_path = ResumableUploadedFileMeta::path(&upload_dir);
+        let meta_before = std::fs::read(&meta_path).unwrap();
 
-        ResumableUploadedFileMeta::update_meta_file(file_path.clone(), |meta| {
-            meta.chunks.insert(0, ChunkStatus::Ongoing);
+        ResumableUploadedFileMeta::update_meta_file(upload_dir.clone(), |meta| {
+            meta.chunks.set(5, ChunkStatus::Completed);
             Ok(())
         })
         .await
         .unwrap();
 
-        // Simulate the put() handler's check-and-set closure.
-        let result = ResumableUploadedFileMeta::update_meta_file(file_path.clone(), |meta| {
-            match meta.chunks.get(&0) {
-                Some(s) if s.is_ongoing() => return Err(ServerError::ChunkIsOngoing),
-                _ => {}
-            }
-            meta.chunks.insert(0, ChunkStatus::Ongoing);
-            Ok(())
-        })
-        .await;
-
-        assert!(matches!(result, Err(ServerError::ChunkIsOngoing)));
+        assert_eq!(std::fs::read(&meta_path).unwrap(), meta_before);
+        assert_eq!(
+            std::fs::read(chunk_map_path(&upload_dir)).unwrap(),
+            [0, 0b0000_1000, 0]
+        );
+    }
+
+    #[tokio::test]
+    async fn update_meta_file_migrates_legacy_chunks() {
+        let dir = TempDir::new().unwrap();
+        let upload_dir = dir.path().join("upload");
+        std::fs::create_dir_all(&upload_dir).unwrap();
+        std::fs::write(
+            ResumableUploadedFileMeta::path(&upload_dir),
+            r#"{"chunkSize":4,"fileSize":10,"chunks":{"0":2,"1":1,"2":0},"target":"a.bin"}"#,
+        )
+        .unwrap();
+
+        // Readable before the migration already.
+        let meta = read_meta_raw(&upload_dir);
+        assert_eq!(
+            meta.chunks.iter().collect::<Vec<_>>(),
+            [
+                ChunkStatus::Completed,
+                ChunkStatus::Ongoing,
+                ChunkStatus::NotStarted
+            ]
+        );
+
+        ResumableUploadedFileMeta::update_meta_file(upload_dir.clone(), |meta| {
+            meta.chunks.set(2, ChunkStatus::Completed);
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let meta_json =
+            std::fs::read_to_string(ResumableUploadedFileMeta::path(&upload_dir)).unwrap();
+        assert!(!meta_json.contains("chunks"), "{meta_json}");
+        assert_eq!(
+            std::fs::read(chunk_map_path(&upload_dir)).unwrap(),
+            [0b0010_0110]
+        );
+        assert_eq!(read_meta_raw(&upload_dir).target, "a.bin");
+    }
+
+    #[test]
+    fn legacy_chunks_out_of_range_are_broken() {
+        let dir = TempDir::new().unwrap();
+        let upload_dir = dir.path().join("upload");
+        std::fs::create_dir_all(&upload_dir).unwrap();
+        let meta_path = ResumableUploadedFileMeta::path(&upload_dir);
+        std::fs::write(
+            &meta_path,
+            r#"{"chunkSize":4,"fileSize":4,"chunks":{"1":0}}"#,
+        )
+        .unwrap();
+
+        let file = StdFile::open(&meta_path).unwrap();
+        assert!(matches!(
+            ResumableUploadedFileMeta::read_from_file_sync(&upload_dir, &file),
+            Err(ServerError::UploadMetaIsBroken)
+        ));
+    }
+
+    #[test]
+    fn status_reports_missing_chunk_ranges() {
+        use ChunkStatus::*;
+        let meta = fixture(&[Completed, NotStarted, Ongoing, Completed, NotStarted]);
+
+        let status = serde_json::to_value(meta.status()).unwrap();
+
+        assert_eq!(status["totalChunks"], 5);
+        assert_eq!(status["missingChunks"], serde_json::json!([[1, 3], [4, 5]]));
+        assert_eq!(status["chunkSize"], 8);
+        assert!(status.get("chunks").is_none());
+    }
+
+    // ----- State transitions / chunk merge predicate -----
+
+    #[tokio::test]
+    async fn cannot_transition_to_ongoing_twice() {
+        let dir = TempDir::new().unwrap();
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::NotStarted]));
+
+        ResumableUploadedFileMeta::update_meta_file(upload_dir.clone(), |meta| {
+            meta.chunks.set(0, ChunkStatus::Ongoing);
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        // Simulate the put() handler's check-and-set closure.
+        let result = ResumableUploadedFileMeta::update_meta_file(upload_dir.clone(), |meta| {
+            match meta.chunks.get(0) {
+                Some(s) if s.is_ongoing() => return Err(ServerError::ChunkIsOngoing),
+                _ => {}
+            }
+            meta.chunks.set(0, ChunkStatus::Ongoing);
+            Ok(())
+        })
+        .await;
+
+        assert!(matches!(result, Err(ServerError::ChunkIsOngoing)));
     }
 
     #[tokio::test]
     async fn cannot_transition_completed_to_ongoing() {
         let dir = TempDir::new().unwrap();
-        let file_path = dir.path().join("a.bin");
-        write_meta_raw(&file_path, &fixture(&[ChunkStatus::Completed]));
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::Completed]));
 
-        let result = ResumableUploadedFileMeta::update_meta_file(file_path.clone(), |meta| {
-            match meta.chunks.get(&0) {
+        let result = ResumableUploadedFileMeta::update_meta_file(upload_dir.clone(), |meta| {
+            match meta.chunks.get(0) {
                 Some(s) if s.is_completed() => return Err(ServerError::ChunkIsCompleted),
                 _ => {}
             }
-            meta.chunks.insert(0, ChunkStatus::Ongoing);
+            meta.chunks.set(0, ChunkStatus::Ongoing);
             Ok(())
         })
         .await;
@@ -872,35 +1790,35 @@ mod tests {
     #[tokio::test]
     async fn all_completed_predicate_flips_on_last_chunk() {
         let dir = TempDir::new().unwrap();
-        let file_path = dir.path().join("a.bin");
-        write_meta_raw(&file_path, &fixture(&[ChunkStatus::NotStarted; 3]));
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::NotStarted; 3]));
 
         for i in 0..2 {
-            ResumableUploadedFileMeta::update_meta_file(file_path.clone(), move |meta| {
-                meta.chunks.insert(i, ChunkStatus::Completed);
+            ResumableUploadedFileMeta::update_meta_file(upload_dir.clone(), move |meta| {
+                meta.chunks.set(i, ChunkStatus::Completed);
                 Ok(())
             })
             .await
             .unwrap();
             assert!(
-                !read_meta_raw(&file_path)
+                !read_meta_raw(&upload_dir)
                     .chunks
-                    .values()
+                    .iter()
                     .all(|s| s.is_completed()),
                 "predicate must stay false until the final chunk"
             );
         }
 
-        ResumableUploadedFileMeta::update_meta_file(file_path.clone(), |meta| {
-            meta.chunks.insert(2, ChunkStatus::Completed);
+        ResumableUploadedFileMeta::update_meta_file(upload_dir.clone(), |meta| {
+            meta.chunks.set(2, ChunkStatus::Completed);
             Ok(())
         })
         .await
         .unwrap();
         assert!(
-            read_meta_raw(&file_path)
+            read_meta_raw(&upload_dir)
                 .chunks
-                .values()
+                .iter()
                 .all(|s| s.is_completed())
         );
     }
@@ -910,13 +1828,13 @@ mod tests {
     #[tokio::test]
     async fn reset_ongoing_to_not_started_resets_ongoing() {
         let dir = TempDir::new().unwrap();
-        let file_path = dir.path().join("a.bin");
-        write_meta_raw(&file_path, &fixture(&[ChunkStatus::Ongoing]));
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::Ongoing]));
 
-        reset_ongoing_chunk_to_not_started(file_path.clone(), 0).await;
+        reset_ongoing_chunk_to_not_started(upload_dir.clone(), 0).await;
 
         assert_eq!(
-            read_meta_raw(&file_path).chunks[&0],
+            read_meta_raw(&upload_dir).chunks.get(0).unwrap(),
             ChunkStatus::NotStarted
         );
     }
@@ -924,24 +1842,27 @@ mod tests {
     #[tokio::test]
     async fn reset_ongoing_to_not_started_leaves_completed_alone() {
         let dir = TempDir::new().unwrap();
-        let file_path = dir.path().join("a.bin");
-        write_meta_raw(&file_path, &fixture(&[ChunkStatus::Completed]));
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::Completed]));
 
-        reset_ongoing_chunk_to_not_started(file_path.clone(), 0).await;
+        reset_ongoing_chunk_to_not_started(upload_dir.clone(), 0).await;
 
-        assert_eq!(read_meta_raw(&file_path).chunks[&0], ChunkStatus::Completed);
+        assert_eq!(
+            read_meta_raw(&upload_dir).chunks.get(0).unwrap(),
+            ChunkStatus::Completed
+        );
     }
 
     #[tokio::test]
     async fn reset_ongoing_to_not_started_leaves_not_started_alone() {
         let dir = TempDir::new().unwrap();
-        let file_path = dir.path().join("a.bin");
-        write_meta_raw(&file_path, &fixture(&[ChunkStatus::NotStarted]));
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::NotStarted]));
 
-        reset_ongoing_chunk_to_not_started(file_path.clone(), 0).await;
+        reset_ongoing_chunk_to_not_started(upload_dir.clone(), 0).await;
 
         assert_eq!(
-            read_meta_raw(&file_path).chunks[&0],
+            read_meta_raw(&upload_dir).chunks.get(0).unwrap(),
             ChunkStatus::NotStarted
         );
     }
@@ -949,9 +1870,9 @@ mod tests {
     #[tokio::test]
     async fn reset_ongoing_to_not_started_ignores_missing_meta() {
         let dir = TempDir::new().unwrap();
-        let file_path = dir.path().join("does-not-exist.bin");
+        let upload_dir = dir.path().join("does-not-exist");
         // No panic, no observable side effect.
-        reset_ongoing_chunk_to_not_started(file_path, 0).await;
+        reset_ongoing_chunk_to_not_started(upload_dir, 0).await;
     }
 
     // ----- OngoingChunkGuard Drop semantics -----
@@ -959,62 +1880,75 @@ mod tests {
     #[tokio::test]
     async fn guard_drop_without_commit_resets_chunk() {
         let dir = TempDir::new().unwrap();
-        let file_path = dir.path().join("a.bin");
-        write_meta_raw(&file_path, &fixture(&[ChunkStatus::Ongoing]));
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::Ongoing]));
 
         {
-            let _guard = OngoingChunkGuard::new(file_path.clone(), 0);
+            let _guard = OngoingChunkGuard::new(upload_dir.clone(), 0);
             // _guard drops here without commit
         }
 
-        let fp = file_path.clone();
-        await_predicate(|| read_meta_raw(&fp).chunks[&0] == ChunkStatus::NotStarted).await;
+        let dir_clone = upload_dir.clone();
+        await_predicate(|| {
+            read_meta_raw(&dir_clone).chunks.get(0).unwrap() == ChunkStatus::NotStarted
+        })
+        .await;
     }
 
     #[tokio::test]
     async fn guard_commit_prevents_reset() {
         let dir = TempDir::new().unwrap();
-        let file_path = dir.path().join("a.bin");
-        write_meta_raw(&file_path, &fixture(&[ChunkStatus::Ongoing]));
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::Ongoing]));
 
-        let guard = OngoingChunkGuard::new(file_path.clone(), 0);
+        let guard = OngoingChunkGuard::new(upload_dir.clone(), 0);
         guard.commit();
 
         // Give any (incorrectly spawned) cleanup task plenty of time to run.
         tokio::time::sleep(Duration::from_millis(50)).await;
-        assert_eq!(read_meta_raw(&file_path).chunks[&0], ChunkStatus::Ongoing);
+        assert_eq!(
+            read_meta_raw(&upload_dir).chunks.get(0).unwrap(),
+            ChunkStatus::Ongoing
+        );
     }
 
     #[tokio::test]
     async fn guard_drop_does_not_clobber_completed() {
         let dir = TempDir::new().unwrap();
-        let file_path = dir.path().join("a.bin");
-        write_meta_raw(&file_path, &fixture(&[ChunkStatus::Ongoing]));
-        let guard = OngoingChunkGuard::new(file_path.clone(), 0);
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::Ongoing]));
+        let guard = OngoingChunkGuard::new(upload_dir.clone(), 0);
 
         // Race scenario: a retry completed the chunk before our cleanup spawned.
-        write_meta_raw(&file_path, &fixture(&[ChunkStatus::Completed]));
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::Completed]));
         drop(guard);
 
         // Wait long enough for any reset task to have run.
         tokio::time::sleep(Duration::from_millis(50)).await;
-        assert_eq!(read_meta_raw(&file_path).chunks[&0], ChunkStatus::Completed);
+        assert_eq!(
+            read_meta_raw(&upload_dir).chunks.get(0).unwrap(),
+            ChunkStatus::Completed
+        );
     }
 
     // ----- reset_stale_ongoing_chunks (startup scan) -----
 
     #[tokio::test]
-    async fn startup_scan_resets_ongoing_in_nested_dirs() {
+    async fn startup_scan_resets_ongoing_in_every_upload() {
         let dir = TempDir::new().unwrap();
-        let nested = dir.path().join("a").join("b");
-        std::fs::create_dir_all(&nested).unwrap();
-        let file_path = nested.join("file.bin");
-        write_meta_raw(&file_path, &fixture(&[ChunkStatus::Ongoing]));
+        let first = dir.path().join("first");
+        let second = dir.path().join("second");
+        write_meta_raw(&first, &fixture(&[ChunkStatus::Ongoing]));
+        write_meta_raw(&second, &fixture(&[ChunkStatus::Ongoing]));
 
         reset_stale_ongoing_chunks(dir.path()).await;
 
         assert_eq!(
-            read_meta_raw(&file_path).chunks[&0],
+            read_meta_raw(&first).chunks.get(0).unwrap(),
+            ChunkStatus::NotStarted
+        );
+        assert_eq!(
+            read_meta_raw(&second).chunks.get(0).unwrap(),
             ChunkStatus::NotStarted
         );
     }
@@ -1022,9 +1956,9 @@ mod tests {
     #[tokio::test]
     async fn startup_scan_only_touches_ongoing_chunks() {
         let dir = TempDir::new().unwrap();
-        let file_path = dir.path().join("mix.bin");
+        let upload_dir = dir.path().join("mix");
         write_meta_raw(
-            &file_path,
+            &upload_dir,
             &fixture(&[
                 ChunkStatus::NotStarted,
                 ChunkStatus::Ongoing,
@@ -1034,25 +1968,24 @@ mod tests {
 
         reset_stale_ongoing_chunks(dir.path()).await;
 
-        let meta = read_meta_raw(&file_path);
-        assert_eq!(meta.chunks[&0], ChunkStatus::NotStarted);
-        assert_eq!(meta.chunks[&1], ChunkStatus::NotStarted);
-        assert_eq!(meta.chunks[&2], ChunkStatus::Completed);
+        let meta = read_meta_raw(&upload_dir);
+        assert_eq!(meta.chunks.get(0).unwrap(), ChunkStatus::NotStarted);
+        assert_eq!(meta.chunks.get(1).unwrap(), ChunkStatus::NotStarted);
+        assert_eq!(meta.chunks.get(2).unwrap(), ChunkStatus::Completed);
     }
 
     #[tokio::test]
-    async fn startup_scan_ignores_non_meta_files() {
+    async fn startup_scan_ignores_dirs_without_meta() {
         let dir = TempDir::new().unwrap();
-        std::fs::write(dir.path().join("payload.bin.resumable-upload"), b"data").unwrap();
+        let half_created = dir.path().join("half-created");
+        std::fs::create_dir(&half_created).unwrap();
+        std::fs::write(data_path(&half_created), b"data").unwrap();
         std::fs::write(dir.path().join("unrelated.txt"), b"x").unwrap();
 
         // Should not panic, should not touch unrelated files.
         reset_stale_ongoing_chunks(dir.path()).await;
 
-        assert_eq!(
-            std::fs::read(dir.path().join("payload.bin.resumable-upload")).unwrap(),
-            b"data"
-        );
+        assert_eq!(std::fs::read(data_path(&half_created)).unwrap(), b"data");
         assert_eq!(
             std::fs::read(dir.path().join("unrelated.txt")).unwrap(),
             b"x"
@@ -1070,20 +2003,21 @@ mod tests {
     #[tokio::test]
     async fn startup_scan_skips_corrupt_meta_and_processes_valid() {
         let dir = TempDir::new().unwrap();
-        let corrupt_path = dir.path().join("corrupt.bin");
+        let corrupt_dir = dir.path().join("corrupt");
+        std::fs::create_dir(&corrupt_dir).unwrap();
         std::fs::write(
-            ResumableUploadedFileMeta::path(&corrupt_path),
+            ResumableUploadedFileMeta::path(&corrupt_dir),
             b"not json at all",
         )
         .unwrap();
 
-        let valid_path = dir.path().join("valid.bin");
-        write_meta_raw(&valid_path, &fixture(&[ChunkStatus::Ongoing]));
+        let valid_dir = dir.path().join("valid");
+        write_meta_raw(&valid_dir, &fixture(&[ChunkStatus::Ongoing]));
 
         reset_stale_ongoing_chunks(dir.path()).await;
 
         assert_eq!(
-            read_meta_raw(&valid_path).chunks[&0],
+            read_meta_raw(&valid_dir).chunks.get(0).unwrap(),
             ChunkStatus::NotStarted
         );
     }
@@ -1095,6 +2029,540 @@ mod tests {
         reset_stale_ongoing_chunks(dir.path()).await;
     }
 
+    #[test]
+    fn list_uploads_skips_unreadable_metas() {
+        let dir = TempDir::new().unwrap();
+        let mut meta = fixture(&[ChunkStatus::Completed, ChunkStatus::NotStarted]);
+        meta.target = "docs/a.bin".to_string();
+        write_meta_raw(&dir.path().join("valid"), &meta);
+        std::fs::create_dir(dir.path().join("corrupt")).unwrap();
+        std::fs::write(
+            ResumableUploadedFileMeta::path(dir.path().join("corrupt")),
+            b"{",
+        )
+        .unwrap();
+
+        let uploads = list_uploads_sync(dir.path());
+
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0].0, dir.path().join("valid"));
+        assert_eq!(uploads[0].1.target(), "docs/a.bin");
+        assert_eq!(uploads[0].1.progress(), 50);
+    }
+
+    // ----- chunk digests -----
+
+    const ABCD_SHA256_HEX: &str =
+        "88d4266fd4e6338d13b845fcf289579d209c897823b9217da3e161936f031589";
+
+    fn digest_headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn abcd_digest() -> [u8; 32] {
+        Sha256::digest(b"abcd").into()
+    }
+
+    #[test]
+    fn chunk_digest_is_optional() {
+        assert_eq!(expected_chunk_digest(&HeaderMap::new()).unwrap(), None);
+    }
+
+    #[test]
+    fn parses_hex_chunk_digest() {
+        let headers = digest_headers(&[("resumable-upload-chunk-sha256", ABCD_SHA256_HEX)]);
+        assert_eq!(
+            expected_chunk_digest(&headers).unwrap(),
+            Some(abcd_digest())
+        );
+    }
+
+    #[test]
+    fn parses_content_digest() {
+        let headers = digest_headers(&[(
+            "content-digest",
+            "sha-512=:AAAA:, sha-256=:iNQmb9TmM40TuEX88olXnSCciXgjuSF9o+Fhk28DFYk=:",
+        )]);
+        assert_eq!(
+            expected_chunk_digest(&headers).unwrap(),
+            Some(abcd_digest())
+        );
+    }
+
+    #[test]
+    fn ignores_content_digest_without_sha256() {
+        let headers = digest_headers(&[("content-digest", "sha-512=:AAAA:")]);
+        assert_eq!(expected_chunk_digest(&headers).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_malformed_chunk_digests() {
+        for headers in [
+            digest_headers(&[("resumable-upload-chunk-sha256", "abcd")]),
+            digest_headers(&[("content-digest", "sha-256=iNQmb9Tm")]),
+            digest_headers(&[("content-digest", "sha-256=:AAAA:")]),
+            digest_headers(&[
+                ("resumable-upload-chunk-sha256", ABCD_SHA256_HEX),
+                (
+                    "content-digest",
+                    "sha-256=:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=:",
+                ),
+            ]),
+        ] {
+            assert!(matches!(
+                expected_chunk_digest(&headers),
+                Err(ServerError::InvalidChunkDigest)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_accepts_chunk_matching_digest() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("data");
+        let file = Arc::new(StdFile::create(&path).unwrap());
+
+        stream_body_to_file(file, 0, 4, Some(abcd_digest()), Body::from("abcd"))
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"abcd");
+    }
+
+    #[tokio::test]
+    async fn stream_rejects_chunk_not_matching_digest() {
+        let dir = TempDir::new().unwrap();
+        let file = Arc::new(StdFile::create(dir.path().join("data")).unwrap());
+
+        let result = stream_body_to_file(file, 0, 4, Some(abcd_digest()), Body::from("abce")).await;
+
+        assert!(matches!(result, Err(ServerError::ChunkDigestMismatch)));
+    }
+
+    // ----- quarantine -----
+
+    #[tokio::test]
+    async fn quarantine_replaces_earlier_quarantined_upload() {
+        let dir = TempDir::new().unwrap();
+        let staging_root = dir.path().join("uploads");
+        let quarantine_root = dir.path().join("quarantine");
+        let upload_dir = staging_root.join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::Completed]));
+        std::fs::write(data_path(&upload_dir), b"new").unwrap();
+        std::fs::create_dir_all(quarantine_root.join("upload")).unwrap();
+        std::fs::write(data_path(&quarantine_root.join("upload")), b"old").unwrap();
+
+        let quarantined = quarantine(&upload_dir, &quarantine_root).await.unwrap();
+
+        assert_eq!(quarantined, quarantine_root.join("upload"));
+        assert!(!upload_dir.exists());
+        assert_eq!(std::fs::read(data_path(&quarantined)).unwrap(), b"new");
+        assert!(ResumableUploadedFileMeta::path(&quarantined).exists());
+    }
+
+    #[tokio::test]
+    async fn sweep_covers_quarantined_uploads() {
+        let store = TempDir::new().unwrap();
+        let staged = staging_root(store.path()).join("staged");
+        let quarantined = quarantine_root(store.path()).join("quarantined");
+        for upload_dir in [&staged, &quarantined] {
+            write_meta_raw(upload_dir, &fixture(&[ChunkStatus::Completed]));
+            age_upload(upload_dir, Duration::from_secs(120));
+        }
+
+        let mut removed =
+            sweep_expired_uploads_in_store(store.path(), Duration::from_secs(60)).await;
+        removed.sort();
+
+        assert_eq!(removed, vec![quarantined, staged]);
+    }
+
+    #[test]
+    fn meta_records_expected_hash() {
+        let mut meta = fixture(&[ChunkStatus::NotStarted]);
+        assert!(!serde_json::to_string(&meta).unwrap().contains("hash"));
+
+        meta.hash = Some(FileHash::Sha256([0; 32]));
+        let json = serde_json::to_string(&meta).unwrap();
+        let meta: ResumableUploadedFileMeta = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(meta.hash, Some(FileHash::Sha256([0; 32])));
+    }
+
+    // ----- negotiate_chunk_size -----
+
+    #[test]
+    fn negotiated_chunk_size_is_clamped() {
+        const MB: usize = 1024 * 1024;
+
+        assert_eq!(negotiate_chunk_size(None, 8 * MB, MB, 64 * MB), 8 * MB);
+        assert_eq!(
+            negotiate_chunk_size(Some(2 * MB), 8 * MB, MB, 64 * MB),
+            2 * MB
+        );
+        assert_eq!(negotiate_chunk_size(Some(1), 8 * MB, MB, 64 * MB), MB);
+        assert_eq!(
+            negotiate_chunk_size(Some(usize::MAX), 8 * MB, MB, 64 * MB),
+            64 * MB
+        );
+    }
+
+    // ----- appendable uploads -----
+
+    #[test]
+    fn append_tracks_offset_and_chunks() {
+        // 10 bytes in chunks of 4: [0, 4), [4, 8), [8, 10).
+        let mut meta = ResumableUploadedFileMeta::new_appendable(4, 10);
+
+        assert!(meta.begin_append(0).unwrap());
+        assert!(meta.chunks.get(0).unwrap().is_ongoing());
+        meta.end_append(6);
+        assert_eq!(meta.offset(), Some(6));
+        assert!(meta.chunks.get(0).unwrap().is_completed());
+        assert!(meta.chunks.get(1).unwrap().is_not_started());
+
+        assert!(meta.begin_append(6).unwrap());
+        assert!(meta.chunks.get(1).unwrap().is_ongoing());
+        meta.end_append(10);
+        assert!(meta.chunks.iter().all(|status| status.is_completed()));
+
+        assert!(!meta.begin_append(10).unwrap());
+    }
+
+    #[test]
+    fn append_rejects_wrong_offset_and_concurrent_appends() {
+        let mut meta = ResumableUploadedFileMeta::new_appendable(4, 10);
+
+        assert!(matches!(
+            meta.begin_append(4),
+            Err(ServerError::UploadOffsetMismatch)
+        ));
+        assert!(meta.begin_append(0).unwrap());
+        assert!(matches!(
+            meta.begin_append(0),
+            Err(ServerError::UploadIsBusy)
+        ));
+
+        let mut chunked = ResumableUploadedFileMeta::new(4, 10);
+        assert!(matches!(
+            chunked.begin_append(0),
+            Err(ServerError::NotAnAppendableUpload)
+        ));
+    }
+
+    // ----- remove_upload -----
+
+    #[tokio::test]
+    async fn remove_upload_deletes_staged_files() {
+        let dir = TempDir::new().unwrap();
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(
+            &upload_dir,
+            &fixture(&[ChunkStatus::Completed, ChunkStatus::NotStarted]),
+        );
+        std::fs::write(data_path(&upload_dir), b"data").unwrap();
+
+        remove_upload(upload_dir.clone()).await.unwrap();
+
+        assert!(!upload_dir.exists());
+    }
+
+    #[tokio::test]
+    async fn remove_upload_refuses_while_chunk_ongoing() {
+        let dir = TempDir::new().unwrap();
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(
+            &upload_dir,
+            &fixture(&[ChunkStatus::Completed, ChunkStatus::Ongoing]),
+        );
+
+        let result = remove_upload(upload_dir.clone()).await;
+
+        assert!(matches!(result, Err(ServerError::UploadHasOngoingChunks)));
+        assert!(ResumableUploadedFileMeta::path(&upload_dir).exists());
+    }
+
+    #[tokio::test]
+    async fn remove_upload_removes_broken_meta() {
+        let dir = TempDir::new().unwrap();
+        let upload_dir = dir.path().join("upload");
+        std::fs::create_dir(&upload_dir).unwrap();
+        std::fs::write(ResumableUploadedFileMeta::path(&upload_dir), b"{").unwrap();
+
+        remove_upload(upload_dir.clone()).await.unwrap();
+
+        assert!(!upload_dir.exists());
+    }
+
+    #[tokio::test]
+    async fn remove_upload_reports_missing_upload() {
+        let dir = TempDir::new().unwrap();
+
+        let result = remove_upload(dir.path().join("upload")).await;
+
+        assert!(matches!(result, Err(ServerError::FileIsNotCreated)));
+    }
+
+    // ----- migrate_legacy_uploads -----
+
+    #[test]
+    fn migrates_uploads_of_older_versions() {
+        let store = TempDir::new().unwrap();
+        let docs = store.path().join("docs");
+        std::fs::create_dir(&docs).unwrap();
+        let legacy_meta = docs.join("a.txt.resumable-meta");
+        std::fs::write(
+            &legacy_meta,
+            br#"{"chunkSize":8,"fileSize":16,"chunks":{"0":2,"1":1}}"#,
+        )
+        .unwrap();
+        let legacy_data = docs.join("a.txt.resumable-upload");
+        std::fs::write(&legacy_data, b"0123456789abcdef").unwrap();
+        let active_at = SystemTime::now() - Duration::from_secs(60 * 60);
+        for path in [&legacy_meta, &legacy_data] {
+            StdFile::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(active_at)
+                .unwrap();
+        }
+        std::fs::write(docs.join("notes.resumable-meta"), b"mine").unwrap();
+
+        migrate_legacy_uploads_sync(store.path());
+
+        let upload_dir = upload_dir(store.path(), "docs/a.txt");
+        let meta = read_meta_raw(&upload_dir);
+        assert_eq!(meta.target(), "docs/a.txt");
+        assert_eq!(meta.conflict, ConflictPolicy::Overwrite);
+        assert_eq!(
+            meta.chunk_statuses().collect::<Vec<_>>(),
+            [ChunkStatus::Completed, ChunkStatus::Ongoing]
+        );
+        assert_eq!(
+            std::fs::read(data_path(&upload_dir)).unwrap(),
+            b"0123456789abcdef"
+        );
+        assert_eq!(last_activity(&upload_dir), Some(active_at));
+        assert!(!legacy_meta.exists());
+        assert!(!legacy_data.exists());
+        assert!(docs.join("notes.resumable-meta").exists());
+
+        // The store is only walked once.
+        std::fs::write(docs.join("b.txt.resumable-meta"), b"{}").unwrap();
+        std::fs::write(docs.join("b.txt.resumable-upload"), b"").unwrap();
+        migrate_legacy_uploads_sync(store.path());
+        assert!(docs.join("b.txt.resumable-meta").exists());
+    }
+
+    #[tokio::test]
+    async fn startup_completes_migration_of_0_4_0_metas() {
+        let store = TempDir::new().unwrap();
+        std::fs::write(
+            store.path().join("a.bin.resumable-meta"),
+            br#"{"chunkSize":4,"fileSize":10,"chunks":{"0":2,"1":1,"2":0}}"#,
+        )
+        .unwrap();
+        std::fs::write(store.path().join("a.bin.resumable-upload"), [0; 10]).unwrap();
+
+        // What the server does when it starts.
+        migrate_legacy_uploads(store.path()).await;
+        reset_stale_ongoing_chunks(&staging_root(store.path())).await;
+
+        let upload_dir = upload_dir(store.path(), "a.bin");
+        let meta_json =
+            std::fs::read_to_string(ResumableUploadedFileMeta::path(&upload_dir)).unwrap();
+        assert!(!meta_json.contains("chunks"), "{meta_json}");
+        assert_eq!(
+            read_meta_raw(&upload_dir).chunks.iter().collect::<Vec<_>>(),
+            [
+                ChunkStatus::Completed,
+                ChunkStatus::NotStarted,
+                ChunkStatus::NotStarted
+            ]
+        );
+    }
+
+    #[test]
+    fn migration_drops_uploads_superseded_after_upgrading() {
+        let store = TempDir::new().unwrap();
+        let upload_dir = upload_dir(store.path(), "a.txt");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::NotStarted]));
+        std::fs::write(store.path().join("a.txt.resumable-meta"), b"{}").unwrap();
+        std::fs::write(store.path().join("a.txt.resumable-upload"), b"old").unwrap();
+
+        migrate_legacy_uploads_sync(store.path());
+
+        assert!(!store.path().join("a.txt.resumable-meta").exists());
+        assert!(!store.path().join("a.txt.resumable-upload").exists());
+        assert_eq!(
+            read_meta_raw(&upload_dir)
+                .chunk_statuses()
+                .collect::<Vec<_>>(),
+            [ChunkStatus::NotStarted]
+        );
+    }
+
+    // ----- finish_upload -----
+
+    #[tokio::test]
+    async fn finish_upload_drops_upload_conflicting_at_completion() {
+        let dir = TempDir::new().unwrap();
+        let upload_dir = dir.path().join("upload");
+        let mut meta = fixture(&[ChunkStatus::Completed]);
+        meta.set_conflict(ConflictPolicy::Reject);
+        write_meta_raw(&upload_dir, &meta);
+        std::fs::write(data_path(&upload_dir), b"uploaded").unwrap();
+        let file_path = dir.path().join("file.txt");
+        std::fs::write(&file_path, b"existing").unwrap();
+
+        let result = finish_upload(&upload_dir, &file_path, &meta, dir.path()).await;
+
+        assert!(matches!(result, Err(ServerError::FileConflict)));
+        assert!(!upload_dir.exists());
+        assert_eq!(std::fs::read(&file_path).unwrap(), b"existing");
+    }
+
+    #[tokio::test]
+    async fn finish_upload_places_file_and_removes_upload() {
+        let dir = TempDir::new().unwrap();
+        let upload_dir = dir.path().join("upload");
+        let mut meta = fixture(&[ChunkStatus::Completed]);
+        meta.set_conflict(ConflictPolicy::Reject);
+        write_meta_raw(&upload_dir, &meta);
+        std::fs::write(data_path(&upload_dir), b"uploaded").unwrap();
+        let file_path = dir.path().join("sub").join("file.txt");
+
+        let stored = finish_upload(&upload_dir, &file_path, &meta, dir.path()).await;
+
+        assert_eq!(
+            stored.unwrap(),
+            format!("sub{}file.txt", std::path::MAIN_SEPARATOR)
+        );
+        assert!(!upload_dir.exists());
+        assert_eq!(std::fs::read(&file_path).unwrap(), b"uploaded");
+    }
+
+    // ----- sweep_expired_uploads -----
+
+    /// Backdate every file of the upload in `upload_dir`, and the directory
+    /// itself, by `age`.
+    fn age_upload(upload_dir: &StdPath, age: Duration) {
+        let then = SystemTime::now() - age;
+        for path in [
+            ResumableUploadedFileMeta::path(upload_dir),
+            chunk_map_path(upload_dir),
+            data_path(upload_dir),
+            upload_dir.to_path_buf(),
+        ] {
+            if let Ok(file) = StdFile::open(&path) {
+                file.set_modified(then).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn sweep_removes_only_expired_uploads() {
+        let dir = TempDir::new().unwrap();
+        let expired = dir.path().join("expired");
+        let fresh = dir.path().join("fresh");
+        for upload_dir in [&expired, &fresh] {
+            write_meta_raw(upload_dir, &fixture(&[ChunkStatus::NotStarted]));
+            std::fs::write(data_path(upload_dir), b"data").unwrap();
+        }
+        age_upload(&expired, Duration::from_secs(120));
+        age_upload(&fresh, Duration::from_secs(30));
+
+        let removed = sweep_expired_uploads(dir.path(), Duration::from_secs(60)).await;
+
+        assert_eq!(removed, vec![expired.clone()]);
+        assert!(!expired.exists());
+        assert!(fresh.exists());
+    }
+
+    #[tokio::test]
+    async fn sweep_keeps_upload_with_recent_chunk_writes() {
+        let dir = TempDir::new().unwrap();
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::Ongoing]));
+        std::fs::write(data_path(&upload_dir), b"data").unwrap();
+        age_upload(&upload_dir, Duration::from_secs(120));
+        // A chunk still streaming only touches the data file.
+        StdFile::open(data_path(&upload_dir))
+            .unwrap()
+            .set_modified(SystemTime::now())
+            .unwrap();
+
+        let removed = sweep_expired_uploads(dir.path(), Duration::from_secs(60)).await;
+
+        assert!(removed.is_empty());
+        assert!(upload_dir.exists());
+    }
+
+    #[tokio::test]
+    async fn sweep_keeps_expired_upload_with_ongoing_chunk() {
+        let dir = TempDir::new().unwrap();
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(
+            &upload_dir,
+            &fixture(&[ChunkStatus::Completed, ChunkStatus::Ongoing]),
+        );
+        std::fs::write(data_path(&upload_dir), b"data").unwrap();
+        age_upload(&upload_dir, Duration::from_secs(120));
+
+        let removed = sweep_expired_uploads(dir.path(), Duration::from_secs(60)).await;
+
+        assert!(removed.is_empty());
+        assert!(upload_dir.exists());
+    }
+
+    #[tokio::test]
+    async fn update_meta_file_reports_swept_upload_as_missing() {
+        let dir = TempDir::new().unwrap();
+        let upload_dir = dir.path().join("upload");
+        write_meta_raw(&upload_dir, &fixture(&[ChunkStatus::NotStarted]));
+        age_upload(&upload_dir, Duration::from_secs(120));
+        sweep_expired_uploads(dir.path(), Duration::from_secs(60)).await;
+
+        let result = ResumableUploadedFileMeta::update_meta_file(&upload_dir, |meta| {
+            meta.chunks.set(0, ChunkStatus::Ongoing);
+            Ok(())
+        })
+        .await;
+
+        assert!(matches!(result, Err(ServerError::FileIsNotCreated)));
+    }
+
+    #[tokio::test]
+    async fn sweep_removes_expired_dirs_without_meta() {
+        let dir = TempDir::new().unwrap();
+        let half_created = dir.path().join("half-created");
+        std::fs::create_dir(&half_created).unwrap();
+        age_upload(&half_created, Duration::from_secs(120));
+        std::fs::write(dir.path().join("unrelated.txt"), b"x").unwrap();
+
+        let removed = sweep_expired_uploads(dir.path(), Duration::from_secs(60)).await;
+
+        assert_eq!(removed, vec![half_created]);
+        assert!(dir.path().join("unrelated.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn sweep_tolerates_missing_root() {
+        let dir = TempDir::new().unwrap();
+        let missing = dir.path().join("does-not-exist");
+
+        assert!(
+            sweep_expired_uploads(&missing, Duration::from_secs(60))
+                .await
+                .is_empty()
+        );
+    }
+
     // ----- get_or_create_in_map (Weak per-upload semaphore map) -----
 
     #[test]
@@ -0,0 +1,172 @@
+use std::path::Path;
+
+use axum::extract::Query;
+use axum::http::header::HeaderMap;
+use axum::response::{Html, IntoResponse, Json};
+use sailfish::TemplateOnce;
+use serde::Deserialize;
+use serde_json::json;
+use time::OffsetDateTime;
+use tokio::task::spawn_blocking;
+
+use crate::auth::Claims;
+use crate::config::CONFIG;
+use crate::errors::ServerError;
+use crate::files::{self, ListingFormat, listing_format};
+use crate::role::Capability;
+use crate::templates;
+use crate::upload::{self, ChunkStatus, ResumableUploadedFileMeta, staging_root};
+
+pub const ROUTE_PATH: &str = "/uploads";
+
+#[derive(Deserialize)]
+pub struct UploadsGetQuery {
+    /// Overrides the format negotiated via the `Accept` header.
+    format: Option<ListingFormat>,
+}
+
+/// List every resumable upload in progress in the user's root.
+pub async fn get(
+    claims: Claims,
+    Query(query): Query<UploadsGetQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ServerError> {
+    claims.require(Capability::Admin)?;
+    let root = claims
+        .root()
+        .strip_prefix(&CONFIG.store_path)
+        .unwrap_or(Path::new(""));
+    let entries =
+        spawn_blocking(move || list_pending_uploads(&staging_root(&CONFIG.store_path), root))
+            .await?;
+
+    if listing_format(&headers, query.format) == ListingFormat::Json {
+        return Ok(Json(json!({ "uploads": entries })).into_response());
+    }
+
+    let uploads_template = templates::Uploads {
+        claims,
+        files_path_prefix: files::ROUTE_PATH_ROOT,
+        entries,
+    };
+
+    Ok(Html(uploads_template.render_once()?).into_response())
+}
+
+/// Summarize the uploads staged under `staging_root` that target files inside
+/// `root`, a path relative to the store root, sorted by their path relative to
+/// `root`.
+fn list_pending_uploads(staging_root: &Path, root: &Path) -> Vec<templates::UploadsEntry> {
+    let mut entries: Vec<_> = upload::list_uploads_sync(staging_root)
+        .into_iter()
+        .filter_map(|(upload_dir, meta)| {
+            let path = Path::new(meta.target()).strip_prefix(root).ok()?;
+            let mut entry = summarize(&meta, upload::last_activity(&upload_dir));
+            entry.path = path.to_string_lossy().into_owned();
+            Some(entry)
+        })
+        .collect();
+    entries.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+    entries
+}
+
+fn summarize(
+    meta: &ResumableUploadedFileMeta,
+    last_activity: Option<std::time::SystemTime>,
+) -> templates::UploadsEntry {
+    let count = |status: ChunkStatus| {
+        meta.chunk_statuses()
+            .filter(|&chunk_status| chunk_status == status)
+            .count()
+    };
+
+    templates::UploadsEntry {
+        path: meta.target().to_string(),
+        file_size: meta.file_size(),
+        chunk_size: meta.chunk_size(),
+        completed_chunks: count(ChunkStatus::Completed),
+        ongoing_chunks: count(ChunkStatus::Ongoing),
+        not_started_chunks: count(ChunkStatus::NotStarted),
+        last_activity: last_activity
+            .map(OffsetDateTime::from)
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn stage(staging_root: &Path, name: &str, meta: &str) {
+        let upload_dir = staging_root.join(name);
+        std::fs::create_dir_all(&upload_dir).unwrap();
+        std::fs::write(ResumableUploadedFileMeta::path(&upload_dir), meta).unwrap();
+    }
+
+    #[test]
+    fn summarizes_chunk_states() {
+        let dir = TempDir::new().unwrap();
+        stage(
+            dir.path(),
+            "upload",
+            r#"{"chunkSize":4,"fileSize":10,"chunks":{"0":2,"1":1,"2":0},"target":"docs/a.bin"}"#,
+        );
+
+        let entries = list_pending_uploads(dir.path(), Path::new(""));
+
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.path, "docs/a.bin");
+        assert_eq!(entry.file_size, 10);
+        assert_eq!(entry.chunk_size, 4);
+        assert_eq!(entry.completed_chunks, 1);
+        assert_eq!(entry.ongoing_chunks, 1);
+        assert_eq!(entry.not_started_chunks, 1);
+        assert_eq!(entry.total_chunks(), 3);
+        assert!(entry.last_activity > OffsetDateTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn sorts_by_target_and_skips_broken_uploads() {
+        let dir = TempDir::new().unwrap();
+        let meta = |target: &str| {
+            format!(r#"{{"chunkSize":4,"fileSize":4,"chunks":{{"0":0}},"target":"{target}"}}"#)
+        };
+        stage(dir.path(), "1", &meta("z.bin"));
+        stage(dir.path(), "2", &meta("a/b.bin"));
+        stage(dir.path(), "3", "{");
+
+        let paths: Vec<_> = list_pending_uploads(dir.path(), Path::new(""))
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+
+        assert_eq!(paths, ["a/b.bin", "z.bin"]);
+    }
+
+    #[test]
+    fn tolerates_missing_staging_root() {
+        let dir = TempDir::new().unwrap();
+
+        assert!(list_pending_uploads(&dir.path().join("does-not-exist"), Path::new("")).is_empty());
+    }
+
+    #[test]
+    fn only_lists_uploads_inside_the_root() {
+        let dir = TempDir::new().unwrap();
+        let meta = |target: &str| {
+            format!(r#"{{"chunkSize":4,"fileSize":4,"chunks":{{"0":0}},"target":"{target}"}}"#)
+        };
+        stage(dir.path(), "1", &meta("alice/docs/a.bin"));
+        stage(dir.path(), "2", &meta("alice.bin"));
+        stage(dir.path(), "3", &meta("bob/b.bin"));
+
+        let paths: Vec<_> = list_pending_uploads(dir.path(), Path::new("alice"))
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+
+        assert_eq!(paths, ["docs/a.bin"]);
+    }
+}
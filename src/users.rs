@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{IsTerminal, stdin};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use anyhow::{Context, Result, bail};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2};
+use rand::{RngExt, rng};
+use serde::Deserialize;
+
+use crate::acl::{AccessRule, Acl};
+use crate::role::Role;
+use crate::safe_path::safe_join;
+
+/// The users file, e.g.
+///
+/// ```toml
+/// [users.alice]
+/// password_hash = "$argon2id$v=19$m=19456,t=2,p=1$..."
+/// root = "contractors/alice"
+/// role = "uploader"
+/// rules = [
+///     { path = "public/**", allow = ["read"] },
+///     { path = "incoming/alice/**", allow = ["read", "write"] },
+/// ]
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UsersFile {
+    #[serde(default)]
+    users: HashMap<String, UserEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UserEntry {
+    password_hash: String,
+    /// Directory the user is confined to, relative to the store path.
+    root: Option<String>,
+    #[serde(default)]
+    role: Role,
+    /// Paths relative to the store path, and what the user may do there.
+    #[serde(default)]
+    rules: Vec<AccessRule>,
+}
+
+struct User {
+    password_hash: String,
+    root: PathBuf,
+    role: Role,
+    acl: Acl,
+}
+
+/// Verified against when the username is unknown, so a failed login takes as
+/// long whether or not the user exists.
+static DUMMY_HASH: LazyLock<String> =
+    LazyLock::new(|| hash_password("").expect("Failed to hash the dummy password"));
+
+/// The users allowed to log in, with their argon2 password hashes and the
+/// part of the store each of them can access.
+#[derive(Default)]
+pub struct Users {
+    users: HashMap<String, User>,
+}
+impl Users {
+    /// Load the users file at `path`. Users without a `root` can access the
+    /// whole `store_path`, or only `{store_path}/{username}` with `user_homes`.
+    pub fn from_file(path: &Path, store_path: &Path, user_homes: bool) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read users file `{}`", path.display()))?;
+        Self::from_toml(&content, store_path, user_homes)
+            .with_context(|| format!("Invalid users file `{}`", path.display()))
+    }
+
+    fn from_toml(content: &str, store_path: &Path, user_homes: bool) -> Result<Self> {
+        let users_file: UsersFile = toml::from_str(content)?;
+        if users_file.users.is_empty() {
+            bail!("No users are defined");
+        }
+
+        let mut users = HashMap::with_capacity(users_file.users.len());
+        for (username, entry) in users_file.users {
+            check_username(&username)?;
+            let hash = PasswordHash::new(&entry.password_hash)
+                .map_err(|_| anyhow::anyhow!("The password hash of `{username}` is malformed"))?;
+            if Algorithm::try_from(hash.algorithm).is_err() {
+                bail!(
+                    "The password hash of `{username}` is not an argon2 hash, \
+                    create one with --hash-password"
+                );
+            }
+            let root = match entry.root {
+                Some(root) => root,
+                None if user_homes => username.clone(),
+                None => String::new(),
+            };
+            let root = safe_join(store_path, root.trim_matches('/'))
+                .map_err(|_| anyhow::anyhow!("The root of `{username}` is not inside the store"))?;
+            users.insert(
+                username,
+                User {
+                    password_hash: entry.password_hash,
+                    root,
+                    role: entry.role,
+                    acl: Acl::new(entry.rules),
+                },
+            );
+        }
+
+        Ok(Self { users })
+    }
+
+    /// The single user given by `--username` and `--password`, an admin who
+    /// can access the whole `store_path`.
+    pub fn single(username: String, password: &str, store_path: &Path) -> Result<Self> {
+        check_username(&username)?;
+        let user = User {
+            password_hash: hash_password(password)?,
+            root: store_path.to_path_buf(),
+            role: Role::Admin,
+            acl: Acl::default(),
+        };
+        Ok(Self {
+            users: HashMap::from([(username, user)]),
+        })
+    }
+
+    /// The directory `username` is confined to. Every path they send is
+    /// resolved relative to it.
+    pub fn root(&self, username: &str) -> Option<&Path> {
+        self.users.get(username).map(|user| user.root.as_path())
+    }
+
+    pub fn role(&self, username: &str) -> Option<Role> {
+        self.users.get(username).map(|user| user.role)
+    }
+
+    pub fn acl(&self, username: &str) -> Option<&Acl> {
+        self.users.get(username).map(|user| &user.acl)
+    }
+
+    /// Create the roots of the users that don't exist yet.
+    pub fn create_roots(&self) -> std::io::Result<()> {
+        for user in self.users.values() {
+            std::fs::create_dir_all(&user.root)?;
+        }
+        Ok(())
+    }
+
+    /// Check `password` against the hash of `username`. Hashing is slow on
+    /// purpose, so don't call this on an async worker thread.
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        let (known, hash) = match self.users.get(username) {
+            Some(user) => (true, &user.password_hash),
+            None => (false, &*DUMMY_HASH),
+        };
+        // Verified even for unknown users, see `DUMMY_HASH`.
+        let matches = verify_password(password, hash);
+        known && matches
+    }
+}
+impl fmt::Debug for Users {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.users
+                    .iter()
+                    .map(|(username, user)| (username, &user.root)),
+            )
+            .finish()
+    }
+}
+
+/// Hash `password` with argon2id for the users file.
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::encode_b64(&rng().random::<[u8; 16]>())
+        .map_err(|error| anyhow::anyhow!("Failed to create salt: {error}"))?;
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|error| anyhow::anyhow!("Failed to hash password: {error}"))?;
+    Ok(hash.to_string())
+}
+
+/// Check `password` against an argon2 `hash`. A malformed hash matches nothing.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// Read the password for `--hash-password`, prompting for it twice without
+/// echoing it on a terminal, or taking the first line of piped input.
+pub fn read_password() -> Result<String> {
+    let password = if stdin().is_terminal() {
+        let password = rpassword::prompt_password("Password: ")?;
+        if rpassword::prompt_password("Confirm password: ")? != password {
+            bail!("The passwords don't match");
+        }
+        password
+    } else {
+        let mut line = String::new();
+        stdin().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+    if password.is_empty() {
+        bail!("The password must not be empty");
+    }
+    Ok(password)
+}
+
+/// HTTP Basic credentials end the username at the first colon.
+fn check_username(username: &str) -> Result<()> {
+    if username.is_empty() || username.contains(':') {
+        bail!("Invalid username `{username}`, it must not be empty or contain `:`");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::role::Capability;
+
+    fn store() -> &'static Path {
+        Path::new("/store")
+    }
+
+    fn users_file(entries: &[(&str, &str)]) -> String {
+        entries
+            .iter()
+            .map(|(username, hash)| format!("[users.{username}]\npassword_hash = \"{hash}\"\n"))
+            .collect()
+    }
+
+    #[test]
+    fn verifies_passwords_of_known_users() {
+        let alice = hash_password("alice's secret").unwrap();
+        let bob = hash_password("bob's secret").unwrap();
+        let users = Users::from_toml(
+            &users_file(&[("alice", &alice), ("bob", &bob)]),
+            store(),
+            false,
+        )
+        .unwrap();
+
+        assert!(users.verify("alice", "alice's secret"));
+        assert!(users.verify("bob", "bob's secret"));
+        assert!(!users.verify("alice", "bob's secret"));
+        assert!(!users.verify("carol", "alice's secret"));
+        assert!(!users.verify("carol", ""));
+        assert!(users.role("alice").is_some());
+        assert!(users.role("carol").is_none());
+    }
+
+    #[test]
+    fn hashes_with_argon2id_and_random_salts() {
+        let first = hash_password("secret").unwrap();
+        let second = hash_password("secret").unwrap();
+
+        assert!(first.starts_with("$argon2id$"));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn rejects_invalid_users_files() {
+        let hash = hash_password("secret").unwrap();
+
+        assert!(Users::from_toml("", store(), false).is_err());
+        assert!(
+            Users::from_toml("[users.alice]\npassword = \"secret\"\n", store(), false).is_err()
+        );
+        assert!(Users::from_toml(&users_file(&[("alice", "secret")]), store(), false).is_err());
+        assert!(
+            Users::from_toml(
+                &users_file(&[("alice", "$2b$12$abcdefghijklmnopqrstuv")]),
+                store(),
+                false
+            )
+            .is_err()
+        );
+        assert!(Users::from_toml(&users_file(&[("\"a:b\"", &hash)]), store(), false).is_err());
+        assert!(Users::from_toml(&users_file(&[("alice", &hash)]), store(), false).is_ok());
+    }
+
+    #[test]
+    fn single_user_from_plaintext_password() {
+        let users = Users::single("admin".to_string(), "password", store()).unwrap();
+
+        assert!(users.verify("admin", "password"));
+        assert!(!users.verify("admin", "Password"));
+        assert!(Users::single("a:b".to_string(), "password", store()).is_err());
+    }
+
+    #[test]
+    fn resolves_roots_inside_the_store() {
+        let hash = hash_password("secret").unwrap();
+        let content = format!(
+            "[users.alice]\npassword_hash = \"{hash}\"\nroot = \"/contractors/alice/\"\n\
+            [users.bob]\npassword_hash = \"{hash}\"\n"
+        );
+
+        let users = Users::from_toml(&content, store(), false).unwrap();
+        assert_eq!(
+            users.root("alice"),
+            Some(Path::new("/store/contractors/alice"))
+        );
+        assert_eq!(users.root("bob"), Some(Path::new("/store")));
+        assert_eq!(users.root("carol"), None);
+
+        let users = Users::from_toml(&content, store(), true).unwrap();
+        assert_eq!(
+            users.root("alice"),
+            Some(Path::new("/store/contractors/alice"))
+        );
+        assert_eq!(users.root("bob"), Some(Path::new("/store/bob")));
+
+        for root in ["../elsewhere", ".sfs", "a/../../b"] {
+            let content = format!("[users.alice]\npassword_hash = \"{hash}\"\nroot = \"{root}\"\n");
+            assert!(
+                Users::from_toml(&content, store(), false).is_err(),
+                "{root}"
+            );
+        }
+    }
+
+    #[test]
+    fn users_are_admins_unless_given_a_role() {
+        let hash = hash_password("secret").unwrap();
+        let content = format!(
+            "[users.alice]\npassword_hash = \"{hash}\"\nrole = \"viewer\"\n\
+            [users.bob]\npassword_hash = \"{hash}\"\n"
+        );
+
+        let users = Users::from_toml(&content, store(), false).unwrap();
+        assert_eq!(users.role("alice"), Some(Role::Viewer));
+        assert_eq!(users.role("bob"), Some(Role::Admin));
+
+        let content = format!("[users.alice]\npassword_hash = \"{hash}\"\nrole = \"owner\"\n");
+        assert!(Users::from_toml(&content, store(), false).is_err());
+    }
+
+    #[test]
+    fn loads_access_rules() {
+        let hash = hash_password("secret").unwrap();
+        let content = format!(
+            "[users.alice]\npassword_hash = \"{hash}\"\n\
+            rules = [{{ path = \"/public/**\", allow = [\"read\"] }}]\n\
+            [users.bob]\npassword_hash = \"{hash}\"\n"
+        );
+
+        let users = Users::from_toml(&content, store(), false).unwrap();
+        let alice = users.acl("alice").unwrap();
+        assert!(alice.allows(Capability::Read, &["public", "a.txt"]));
+        assert!(!alice.allows(Capability::Write, &["public", "a.txt"]));
+        assert!(!alice.allows(Capability::Read, &["private"]));
+        assert!(
+            users
+                .acl("bob")
+                .unwrap()
+                .allows(Capability::Write, &["private"])
+        );
+
+        for rule in [
+            r#"{ path = "../**", allow = ["read"] }"#,
+            r#"{ path = "**", allow = ["fly"] }"#,
+            r#"{ path = "**", allow = ["read"], deny = ["write"] }"#,
+        ] {
+            let content = format!("[users.alice]\npassword_hash = \"{hash}\"\nrules = [{rule}]\n");
+            assert!(
+                Users::from_toml(&content, store(), false).is_err(),
+                "{rule}"
+            );
+        }
+    }
+}